@@ -9,6 +9,9 @@ cd genesis && cargo build $1 && echo "Genesis built" \
 && cd ../physics && cargo build $1 && echo "Physics built" \
 && cd ../radar && cargo build $1 && echo "Radar built" \
 && cd ../stacktrader-types && cargo build $1 && echo "Stacktrader-types built" \
+&& cd ../trade && cargo build $1 && echo "Trade built" \
+&& cd ../market && cargo build $1 && echo "Market built" \
+&& cd ../contracts && cargo build $1 && echo "Contracts built" \
 
 if [ $? -eq 0 ]
 then
@@ -0,0 +1,653 @@
+//! # Contracts
+//!
+//! The contracts system awaits frames for entities that have a `contract` component. Each time it
+//! encounters such a frame, it will, depending on the contract's status:
+//! - `requested`: draw up a contract at the starbase the player is docked at. This is either a
+//!   delivery (bring some units of a stack type to another starbase) or a mining contract (mine
+//!   some units of a stack type), with a time limit and a reward. A player whose contract for the
+//!   current period is still active gets that same contract back, so abandoning a contract and
+//!   requesting another never draws new terms
+//! - `active`: count down the time limit and update the progress, from the player's inventory
+//!   for a delivery or from the units mined since the contract was issued for a mining contract.
+//!   Once the contract is fulfilled the reward is paid into the player's wallet (and recorded in
+//!   the ledger) and, for deliveries, the goods are taken out of the inventory. A contract that
+//!   runs out of time expires, and a delivery whose destination no longer exists fails
+//! - `completed`, `expired` or `failed`: delete the contract, so the player can request another
+//!
+//! Contracts are numbered from a counter in the KV store. The contract as issued is kept in the
+//! KV store under that number, and it is that copy which is counted down, checked and paid out.
+//! All the system takes from the player's `contract` component is the number, so a player can't
+//! rewrite the terms or the reward of their own contract.
+//!
+//! Units mined are counted from the `mined_{stack_type}` statistics the mining system publishes
+//! on `decs.stats.{shard}.mined_{stack_type}`, so nothing the player had or bought before the
+//! contract was issued counts towards it.
+//!
+//! The reward is paid as a transaction under the contract's number, which is only marked
+//! completed once the wallet has been set. A retried frame or a second contracts system never
+//! pays the same contract twice, and a failure before the wallet is set is retried.
+use decscloud_common::gateway::*;
+use guest::prelude::*;
+use stacktrader_types as trader;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+use trader::components::*;
+use trader::events::{publish_stat, StatIncrement, STAT_LIFETIME_CREDITS};
+use trader::starbase::{self, TradingPost};
+use trader::transaction::{self, Claim};
+use trader::wallet::*;
+
+const STACK_TYPES: [&str; 3] = ["spendy", "tasty", "critical"];
+const MIN_QTY: u32 = 5;
+const MAX_QTY: u32 = 20;
+const DELIVERY_TIME_MS: f64 = 600_000.0;
+const MINING_TIME_MS: f64 = 900_000.0;
+const CONTRACT_REASON: &str = "contract";
+/// The step of completing a contract that pays its reward
+const PAID: &str = "paid";
+/// How long an issued contract is kept after it was last updated, in seconds. This comfortably
+/// outlasts the time limit of any contract
+const ISSUED_TTL: u32 = 24 * 60 * 60;
+/// The length of the period during which a player keeps getting the same contract, in seconds
+const PERIOD_SECONDS: u64 = 60 * 60;
+
+/// A contract as issued to a player, kept in the KV store
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct Issued {
+    entity_id: String,
+    contract: Contract,
+}
+
+/// Receives an entity, shard, elapsed time, etc from an EntityFrame
+/// published on decs.frames.{shard}.{system}, e.g. `decs.frames.the_void.contracts`
+pub(crate) fn handle_frame(
+    ctx: &CapabilitiesContext,
+    msg: guest::prelude::messaging::BrokerMessage,
+) -> CallResult {
+    let subject: Vec<&str> = msg.subject.split('.').collect();
+    if subject.len() != 4 {
+        return Err("Unknown message subject received".into());
+    }
+    let frame: decs::systemmgr::EntityFrame = serde_json::from_slice(&msg.body)?;
+    let contract = match get_contract(ctx, &frame.shard, &frame.entity_id)? {
+        Some(contract) => contract,
+        None => return Ok(vec![]),
+    };
+
+    match contract.status {
+        ContractStatus::Requested => draw_up(ctx, &frame)?,
+        ContractStatus::Active => match get_issued(ctx, &frame.shard, &contract.id)? {
+            Some(ref issued) if issued.entity_id != frame.entity_id => {
+                reject_unknown(ctx, &frame, contract)?
+            }
+            // Finished, but the player's copy hasn't caught up yet
+            Some(issued) if issued.contract.status != ContractStatus::Active => {
+                publish_contract(ctx, &frame.shard, &frame.entity_id, &issued.contract)?
+            }
+            Some(issued) => advance(ctx, &frame, issued.contract)?,
+            None => reject_unknown(ctx, &frame, contract)?,
+        },
+        ContractStatus::Completed | ContractStatus::Expired | ContractStatus::Failed => {
+            publish_contract_delete(ctx, &frame.shard, &frame.entity_id)?
+        }
+    }
+
+    Ok(vec![])
+}
+
+/// Receives the statistics published on `decs.stats.{shard}.{category}` and counts the units
+/// mined towards the miner's active mining contract, if it is for the stack type mined
+pub(crate) fn handle_stat(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> CallResult {
+    let tokens: Vec<_> = msg.subject.split('.').collect();
+    if tokens.len() != 4 {
+        return Err(format!("unknown statistic: {}", msg.subject).into());
+    }
+    let shard = tokens[2];
+    let stack_type = match tokens[3].strip_prefix("mined_") {
+        Some(stack_type) => stack_type,
+        None => return Ok(vec![]), // Only units mined count towards a contract
+    };
+    let stat: StatIncrement = serde_json::from_slice(&msg.body)?;
+    let id = match get_contract(ctx, shard, &stat.entity_id)? {
+        Some(contract) => contract.id,
+        None => return Ok(vec![]),
+    };
+    let counts = match get_issued(ctx, shard, &id)? {
+        Some(issued) => {
+            issued.entity_id == stat.entity_id
+                && issued.contract.status == ContractStatus::Active
+                && issued.contract.kind == ContractKind::Mining
+                && issued.contract.stack_type == stack_type
+        }
+        None => false,
+    };
+    if counts && stat.amount > 0 {
+        let amount = i32::try_from(stat.amount).unwrap_or(i32::MAX);
+        ctx.kv().atomic_add(&mined_key(shard, &id), amount)?;
+    }
+    Ok(vec![])
+}
+
+/// Draw up a contract for a player docked at a starbase, unless the player's contract for the
+/// current period is still active, in which case that is handed back instead
+fn draw_up(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let shard = &frame.shard;
+    let entity = &frame.entity_id;
+    let origin = match starbase::docked_at(ctx, shard, entity)? {
+        Some(origin) => origin,
+        None => {
+            let contract = Contract {
+                status: ContractStatus::Failed,
+                error: Some("contracts are only offered at starbases".to_string()),
+                ..Default::default()
+            };
+            return publish_contract(ctx, shard, entity, &contract);
+        }
+    };
+
+    let period = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / PERIOD_SECONDS;
+    let drawn_key = format!("decs:{}:contracts:drawn:{}:{}", shard, entity, period);
+    let previous = match ctx.kv().get(&drawn_key)? {
+        Some(id) => get_issued(ctx, shard, &id)?.filter(|i| i.entity_id == *entity),
+        None => None,
+    };
+    if let Some(issued) = previous.as_ref() {
+        if issued.contract.status == ContractStatus::Active {
+            return publish_contract(ctx, shard, entity, &issued.contract);
+        }
+    }
+
+    // The terms only depend on the player, the period and the player's previous contract in it
+    let mut hasher = DefaultHasher::new();
+    (entity, period, previous.map(|i| i.contract.id)).hash(&mut hasher);
+    let terms = draw_terms(
+        hasher.finish(),
+        &origin,
+        &starbase::get_starbases(ctx, shard)?,
+    );
+    let id = ctx
+        .kv()
+        .atomic_add(&format!("decs:{}:contracts:next_id", shard), 1)?;
+    let contract = Contract {
+        id: id.to_string(),
+        status: ContractStatus::Active,
+        ..terms
+    };
+    update(ctx, shard, entity, &contract)?;
+    ctx.kv()
+        .set(&drawn_key, &contract.id, Some(PERIOD_SECONDS as u32))?;
+    Ok(())
+}
+
+/// Fail a contract that wasn't issued to the player, leaving it in place for the player to see
+fn reject_unknown(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+    contract: Contract,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let contract = Contract {
+        status: ContractStatus::Failed,
+        error: Some("no such contract was issued to you".to_string()),
+        ..contract
+    };
+    publish_contract(ctx, &frame.shard, &frame.entity_id, &contract)
+}
+
+/// Pick the terms of a contract offered at `origin`. Deliveries go to one of the other starbases
+/// when there is one, and pay extra for the distance travelled
+fn draw_terms(seed: u64, origin: &TradingPost, starbases: &[TradingPost]) -> Contract {
+    let stack_type = STACK_TYPES[(seed % 3) as usize];
+    let qty = MIN_QTY + ((seed >> 8) % u64::from(MAX_QTY - MIN_QTY + 1)) as u32;
+    let value = unit_value(stack_type) * Credits::from(qty);
+    let others: Vec<_> = starbases
+        .iter()
+        .filter(|s| s.entity_id != origin.entity_id)
+        .collect();
+
+    if (seed >> 16) & 1 == 0 || others.is_empty() {
+        Contract {
+            kind: ContractKind::Mining,
+            stack_type: stack_type.to_string(),
+            qty,
+            destination: origin.entity_id.clone(),
+            destination_name: origin.starbase.name.clone(),
+            reward: value * 3 / 2,
+            remaining_ms: MINING_TIME_MS,
+            ..Default::default()
+        }
+    } else {
+        let destination = others[((seed >> 24) % others.len() as u64) as usize];
+        let distance = origin.position.distance_to_3d(&destination.position);
+        Contract {
+            kind: ContractKind::Delivery,
+            stack_type: stack_type.to_string(),
+            qty,
+            destination: destination.entity_id.clone(),
+            destination_name: destination.starbase.name.clone(),
+            reward: value * 2 + distance.round() as Credits,
+            remaining_ms: DELIVERY_TIME_MS,
+            ..Default::default()
+        }
+    }
+}
+
+/// The base value of a unit of the given stack type, the same as the merchant pays
+fn unit_value(stack_type: &str) -> Credits {
+    match stack_type {
+        "critical" => 100,
+        "tasty" => 50,
+        "spendy" => 30,
+        _ => 0,
+    }
+}
+
+/// Count down an active contract and complete it once its terms are met
+fn advance(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+    contract: Contract,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let shard = &frame.shard;
+    let entity = &frame.entity_id;
+    let inventory = get_inventory(ctx, shard, entity)?;
+    let units = match contract.kind {
+        ContractKind::Delivery => units_held(&inventory, &contract.stack_type),
+        ContractKind::Mining => match ctx.kv().get(&mined_key(shard, &contract.id))? {
+            Some(mined) => mined.parse()?,
+            None => 0,
+        },
+    };
+    let contract = Contract {
+        remaining_ms: (contract.remaining_ms - f64::from(frame.elapsed_ms)).max(0.0),
+        progress: units.min(contract.qty),
+        ..contract
+    };
+
+    let fulfilled = contract.progress >= contract.qty
+        && match contract.kind {
+            ContractKind::Mining => true,
+            ContractKind::Delivery => starbase::docked_at(ctx, shard, entity)?
+                .filter(|p| p.entity_id == contract.destination)
+                .is_some(),
+        };
+
+    if fulfilled {
+        complete(ctx, frame, contract, &inventory)
+    } else if contract.remaining_ms <= 0.0 {
+        let contract = Contract {
+            status: ContractStatus::Expired,
+            ..contract
+        };
+        update(ctx, shard, entity, &contract)
+    } else if contract.kind == ContractKind::Delivery
+        && !starbase::get_starbases(ctx, shard)?
+            .iter()
+            .any(|s| s.entity_id == contract.destination)
+    {
+        let contract = Contract {
+            status: ContractStatus::Failed,
+            error: Some(format!("{} no longer exists", contract.destination_name)),
+            ..contract
+        };
+        update(ctx, shard, entity, &contract)
+    } else {
+        update(ctx, shard, entity, &contract)
+    }
+}
+
+/// Pay out a fulfilled contract, then take delivered goods out of the inventory
+fn complete(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+    contract: Contract,
+    inventory: &[(String, InventoryItem)],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let shard = &frame.shard;
+    let entity = &frame.entity_id;
+    match transaction::claim(ctx, shard, super::SYSTEM_NAME, &contract.id)? {
+        Claim::Won => {
+            match pay_reward(ctx, frame, &contract) {
+                Ok(reward) => publish_stat(ctx, shard, entity, STAT_LIFETIME_CREDITS, reward)?,
+                Err(e) => {
+                    // Let the next frame carry on with the payment from wherever this attempt
+                    // stopped
+                    transaction::release(ctx, shard, super::SYSTEM_NAME, &contract.id)?;
+                    return Err(e);
+                }
+            }
+        }
+        // Paid by an earlier attempt that didn't get to record the contract as completed
+        Claim::Completed => {}
+        Claim::InFlight => return Ok(()),
+    }
+
+    let contract = Contract {
+        status: ContractStatus::Completed,
+        ..contract
+    };
+    save_issued(ctx, shard, entity, &contract)?;
+    if contract.kind == ContractKind::Delivery {
+        let (taken, leftover) = take_units(inventory, &contract.stack_type, contract.qty);
+        for rid in taken {
+            publish_inventory_delete(ctx, shard, entity, &rid)?;
+        }
+        if leftover > 0 {
+            let item = InventoryItem {
+                resource: MiningResource {
+                    stack_type: contract.stack_type.clone(),
+                    qty: leftover,
+                },
+                error: None,
+            };
+            publish_inventory_new(ctx, shard, entity, &item)?;
+        }
+    }
+    publish_contract(ctx, shard, entity, &contract)
+}
+
+/// Pay the contract's reward and record it in the ledger, and only then complete the transaction.
+/// The payment is recorded as a step of the transaction along with the balance it left, so a
+/// retry never pays twice and always gets to write the ledger entry. Returns the reward paid
+fn pay_reward(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+    contract: &Contract,
+) -> std::result::Result<Credits, Box<dyn std::error::Error>> {
+    let (shard, entity) = (&frame.shard, &frame.entity_id);
+    let paid = transaction::step(ctx, shard, super::SYSTEM_NAME, &contract.id, PAID)?;
+    let (reward, balance): (Credits, Credits) = match paid {
+        Some(paid) => serde_json::from_str(&paid)?,
+        None => {
+            let mut reward = 0;
+            let wallet = update_wallet(ctx, shard, entity, |wallet| {
+                // An overflowing reward is capped rather than lost, the contract has been fulfilled
+                reward = contract
+                    .reward
+                    .min(Credits::MAX - wallet.credits.max(0))
+                    .max(0);
+                Ok(CreditWallet {
+                    credits: wallet.credits + reward,
+                })
+            })?;
+            let paid = (reward, wallet.credits);
+            transaction::record_step(
+                ctx,
+                shard,
+                super::SYSTEM_NAME,
+                &contract.id,
+                PAID,
+                &serde_json::to_string(&paid)?,
+            )?;
+            paid
+        }
+    };
+    let entry = LedgerEntry {
+        txid: contract.id.clone(),
+        seq_no: frame.seq_no,
+        amount: reward,
+        balance,
+        counterparty: contract.destination.clone(),
+        item: Some(MiningResource {
+            stack_type: contract.stack_type.clone(),
+            qty: contract.qty,
+        }),
+        reason: CONTRACT_REASON.to_string(),
+        ..Default::default()
+    };
+    publish_ledger_entry(ctx, shard, entity, &entry)?;
+    transaction::complete(ctx, shard, super::SYSTEM_NAME, &contract.id)?;
+    Ok(reward)
+}
+
+/// Total units of the given stack type in an inventory, ignoring items flagged with an error
+fn units_held(inventory: &[(String, InventoryItem)], stack_type: &str) -> u32 {
+    inventory
+        .iter()
+        .filter(|(_, item)| item.error.is_none() && item.resource.stack_type == stack_type)
+        .fold(0u32, |total, (_, item)| {
+            total.saturating_add(item.resource.qty)
+        })
+}
+
+/// Pick inventory items of the given stack type until `qty` units are covered, returning their
+/// RIDs and how many units of the last item are left over and should go back into the inventory
+fn take_units(
+    inventory: &[(String, InventoryItem)],
+    stack_type: &str,
+    qty: u32,
+) -> (Vec<String>, u32) {
+    let mut taken = Vec::new();
+    let mut covered = 0u32;
+    for (rid, item) in inventory {
+        if covered >= qty {
+            break;
+        }
+        if item.error.is_none() && item.resource.stack_type == stack_type {
+            taken.push(rid.clone());
+            covered = covered.saturating_add(item.resource.qty);
+        }
+    }
+    (taken, covered.saturating_sub(qty))
+}
+
+fn get_contract(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<Option<Contract>, Box<dyn std::error::Error>> {
+    let key = format!("decs:components:{}:{}:{}", shard, entity, super::CONTRACT);
+    match ctx.kv().get(&key)? {
+        Some(s) => Ok(Some(serde_json::from_str(&s)?)),
+        None => Ok(None),
+    }
+}
+
+/// Retrieve every item in the entity's inventory along with its RID
+fn get_inventory(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<Vec<(String, InventoryItem)>, Box<dyn std::error::Error>> {
+    let key = format!("decs:components:{}:{}:{}", shard, entity, super::INVENTORY);
+    let mut items = Vec::new();
+    for rid in ctx.kv().list_range(&key, 0, -1)? {
+        if let Some(s) = ctx.kv().get(&rid.replace('.', ":"))? {
+            items.push((rid, serde_json::from_str(&s)?));
+        }
+    }
+    Ok(items)
+}
+
+fn issued_key(shard: &str, id: &str) -> String {
+    format!("decs:{}:contracts:issued:{}", shard, id)
+}
+
+/// Key of the count of units mined towards a mining contract
+fn mined_key(shard: &str, id: &str) -> String {
+    format!("decs:{}:contracts:mined:{}", shard, id)
+}
+
+fn get_issued(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    id: &str,
+) -> std::result::Result<Option<Issued>, Box<dyn std::error::Error>> {
+    if id.is_empty() {
+        return Ok(None);
+    }
+    match ctx.kv().get(&issued_key(shard, id))? {
+        Some(s) => Ok(Some(serde_json::from_str(&s)?)),
+        None => Ok(None),
+    }
+}
+
+fn save_issued(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    contract: &Contract,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let issued = Issued {
+        entity_id: entity.to_string(),
+        contract: contract.clone(),
+    };
+    ctx.kv().set(
+        &issued_key(shard, &contract.id),
+        &serde_json::to_string(&issued)?,
+        Some(ISSUED_TTL),
+    )?;
+    if contract.status != ContractStatus::Active {
+        ctx.kv().del_key(&mined_key(shard, &contract.id))?;
+    }
+    Ok(())
+}
+
+/// Record a change to an issued contract, then show it to the player
+fn update(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    contract: &Contract,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    save_issued(ctx, shard, entity, contract)?;
+    publish_contract(ctx, shard, entity, contract)
+}
+
+fn publish_contract(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    contract: &Contract,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let setreq = ResProtocolRequest::Set(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::CONTRACT
+    ));
+    ctx.msg().publish(
+        &setreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({ "params": contract }))?,
+    )?;
+    Ok(())
+}
+
+fn publish_contract_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let rid = format!("decs.components.{}.{}.{}", shard, entity, super::CONTRACT);
+    let delreq = ResProtocolRequest::Delete(rid.clone());
+    ctx.msg().publish(
+        &delreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({"params": {"rid": rid}}))?,
+    )?;
+    Ok(())
+}
+
+fn publish_inventory_new(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    item: &InventoryItem,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let newreq = ResProtocolRequest::New(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::INVENTORY
+    ));
+    ctx.msg().publish(
+        &newreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({ "params": item }))?,
+    )?;
+    Ok(())
+}
+
+fn publish_inventory_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    rid: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let delreq = ResProtocolRequest::Delete(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::INVENTORY
+    ));
+    ctx.msg().publish(
+        &delreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({"params": {"rid": rid}}))?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dock(entity_id: &str, x: f64) -> TradingPost {
+        TradingPost {
+            entity_id: entity_id.to_string(),
+            position: Position::new(x, 0.0, 0.0),
+            starbase: Starbase {
+                name: entity_id.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn item(stack_type: &str, qty: u32) -> InventoryItem {
+        InventoryItem {
+            resource: MiningResource {
+                stack_type: stack_type.to_string(),
+                qty,
+            },
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_draw_terms() {
+        let docks = vec![dock("starbase_0", 0.0), dock("starbase_1", 100.0)];
+        for seed in 0..64u64 {
+            let seed = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            let terms = draw_terms(seed, &docks[0], &docks);
+            assert!(terms.qty >= MIN_QTY && terms.qty <= MAX_QTY);
+            assert!(terms.reward > 0);
+            match terms.kind {
+                ContractKind::Delivery => assert_eq!("starbase_1", terms.destination),
+                ContractKind::Mining => assert_eq!("starbase_0", terms.destination),
+            }
+        }
+
+        // With nowhere else to deliver to, only mining contracts are offered
+        let lonely = draw_terms(0, &docks[0], &docks[..1]);
+        assert_eq!(ContractKind::Mining, lonely.kind);
+    }
+
+    #[test]
+    fn test_take_units() {
+        let inventory = vec![
+            ("inv.1".to_string(), item("critical", 8)),
+            ("inv.2".to_string(), item("tasty", 20)),
+            ("inv.3".to_string(), item("critical", 15)),
+        ];
+        assert_eq!(23, units_held(&inventory, "critical"));
+
+        let (taken, leftover) = take_units(&inventory, "critical", 20);
+        assert_eq!(vec!["inv.1".to_string(), "inv.3".to_string()], taken);
+        assert_eq!(3, leftover);
+    }
+}
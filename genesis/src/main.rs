@@ -6,6 +6,7 @@ extern crate serde_json;
 
 use natsclient::{AuthenticationStyle, Client, ClientOptions};
 use rand::Rng;
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
 use std::fs::File;
@@ -36,6 +37,17 @@ struct Distribution {
     critical: f32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct StarbaseParameters {
+    name: String,
+    position: Point,
+    color: String,
+    #[serde(default)]
+    prices: HashMap<String, f64>,
+    #[serde(default)]
+    beacon_range: Option<f64>, // How far away the starbase shows up on radar, anywhere if absent
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct UniverseParameters {
     from: Point,
@@ -43,11 +55,32 @@ struct UniverseParameters {
     asteroids: u32,
     asteroid_adjs: Vec<String>,
     asteroid_colors: Vec<String>,
+    #[serde(default)]
     starbase_color: String,
+    #[serde(default)]
+    starbases: Vec<StarbaseParameters>,
     shard_name: String,
     shard_capacity: u32,
     max_stack_qty: u32,
     distribution: Distribution,
+    #[serde(default = "default_trade_radius")]
+    trade_radius: f64,
+    #[serde(default = "default_leaderboard_size")]
+    leaderboard_size: usize,
+    #[serde(default = "default_leaderboard_season_days")]
+    leaderboard_season_days: u64,
+}
+
+fn default_trade_radius() -> f64 {
+    5.0
+}
+
+fn default_leaderboard_size() -> usize {
+    10
+}
+
+fn default_leaderboard_season_days() -> u64 {
+    28
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -91,8 +124,24 @@ fn main() -> Result<(), Box<dyn Error>> {
     );
     std::thread::sleep(breather_delay*3);
 
-    create_starbase(&client, &params)?;
-    println!("Created Starbase Alpha at (0,0,0)");
+    // Worlds that predate multiple starbases get the original Starbase Alpha at the origin
+    let default_starbases = vec![StarbaseParameters {
+        name: "Starbase Alpha".to_string(),
+        color: params.starbase_color.clone(),
+        ..Default::default()
+    }];
+    let starbases = if params.starbases.is_empty() {
+        &default_starbases
+    } else {
+        &params.starbases
+    };
+    for (x, starbase) in starbases.iter().enumerate() {
+        create_starbase(&client, &params, starbase, x)?;
+        println!(
+            "Created {} at ({},{},{})",
+            starbase.name, starbase.position.x, starbase.position.y, starbase.position.z
+        );
+    }
 
     Ok(())
 }
@@ -111,6 +160,8 @@ fn create_shard(nats: &Client, params: &UniverseParameters) -> Result<(), Box<dy
         }),
     )?;
     set_shard_metadata(nats, params)?;
+    set_merchant_config(nats, params)?;
+    set_leaderboard_config(nats, params)?;
 
     Ok(())
 }
@@ -134,8 +185,42 @@ fn set_shard_metadata(nats: &Client, params: &UniverseParameters) -> Result<(),
     Ok(())
 }
 
-fn create_starbase(nats: &Client, params: &UniverseParameters) -> Result<(), Box<dyn Error>> {
-    let entity_id = "starbase_0";
+// The merchant's settings are the `merchant` component on the `universe` entity
+fn set_merchant_config(nats: &Client, params: &UniverseParameters) -> Result<(), Box<dyn Error>> {
+    let rid = format!("decs.components.{}.universe.merchant", params.shard_name);
+    create_component(
+        nats,
+        &rid,
+        json!({
+            "trade_radius": params.trade_radius
+        }),
+    )?;
+
+    Ok(())
+}
+
+// The leaderboard's settings are the `leaderboard` component on the `universe` entity
+fn set_leaderboard_config(nats: &Client, params: &UniverseParameters) -> Result<(), Box<dyn Error>> {
+    let rid = format!("decs.components.{}.universe.leaderboard", params.shard_name);
+    create_component(
+        nats,
+        &rid,
+        json!({
+            "size": params.leaderboard_size,
+            "season_days": params.leaderboard_season_days
+        }),
+    )?;
+
+    Ok(())
+}
+
+fn create_starbase(
+    nats: &Client,
+    params: &UniverseParameters,
+    starbase: &StarbaseParameters,
+    idx: usize,
+) -> Result<(), Box<dyn Error>> {
+    let entity_id = format!("starbase_{}", idx);
     create_component(
         nats,
         &format!(
@@ -143,15 +228,15 @@ fn create_starbase(nats: &Client, params: &UniverseParameters) -> Result<(), Box
             params.shard_name, entity_id
         ),
         json!({
-            "x": 0.0,
-            "y": 0.0,
-            "z": 0.0
+            "x": starbase.position.x,
+            "y": starbase.position.y,
+            "z": starbase.position.z
         }),
     )?;
 
     let transponder = json!({"object_type": "starbase",
-                        "display_name": "Starbase Alpha".to_string(),
-                        "color": params.starbase_color});
+                        "display_name": starbase.name,
+                        "color": starbase.color});
 
     create_component(
         nats,
@@ -162,6 +247,24 @@ fn create_starbase(nats: &Client, params: &UniverseParameters) -> Result<(), Box
         transponder,
     )?;
 
+    create_component(
+        nats,
+        &format!(
+            "decs.components.{}.{}.starbase",
+            params.shard_name, entity_id
+        ),
+        json!({
+            "name": starbase.name,
+            "prices": starbase.prices
+        }),
+    )?;
+
+    create_component(
+        nats,
+        &format!("decs.components.{}.{}.beacon", params.shard_name, entity_id),
+        json!({ "range": starbase.beacon_range }),
+    )?;
+
     Ok(())
 }
 
@@ -1,75 +1,731 @@
+//! # Leaderboard
+//!
+//! Ranks the players of each shard in several categories. The `credits` category ranks the
+//! credits currently in each player's wallet and is served at `decs.{shard}.leaderboard`. The
+//! other categories accumulate statistics that mining, the merchant and physics publish on
+//! `decs.stats.{shard}.{category}`, and are served at `decs.{shard}.leaderboard.{category}`.
+//!
+//! Every category is also ranked across all shards at `decs.global.leaderboard[.{category}]`, so
+//! players in different shards can compare themselves. Any change to a shard's ranking is
+//! announced on the global leaderboard as well.
+//!
+//! Each shard's leaderboards are also kept for daily, weekly and season windows, numbered from 1,
+//! at `decs.{shard}.leaderboard.{window}.{n}[.{category}]`. Statistics tallied during a window
+//! count towards it, and a window's credits are the credits earned from sales, market fills and
+//! contracts during it. The window currently running is described by the `decs.{shard}.leaderboard.{window}` model, which
+//! changes whenever the next window starts. Windows last a number of seconds (see
+//! `LeaderboardConfig`), timed by a KV key that expires when the window is up, so they keep time
+//! across restarts. The results of past windows stay archived in the KV store.
+//!
+//! Every score is persisted to the KV store (a set of ranked players per shard and category plus
+//! one key per player's score) and cached in memory. The cache is rebuilt from the KV store
+//! whenever a shard is first seen, including right after a restart, and is periodically resynced
+//! so that multiple leaderboard instances agree. Wallets that have never been scored are picked up
+//! from `decs:{shard}:wallet:entities`. The shards seen so far are kept in the KV store too, so
+//! the global leaderboard can be rebuilt the same way.
+//!
+//! Players are listed by display name: the one in their `profile` component if they have one,
+//! otherwise their transponder's. Names are refreshed on every frame, so a rename shows up as a
+//! change to the player's row like any change in score.
+use crate::ranking::Ranking;
 use decs::gateway::*;
 use guest::prelude::*;
 use stacktrader_types as trader;
 use std::collections::HashMap;
 use std::sync::RwLock;
 use trader::components::*;
+use trader::events::*;
+use trader::transaction;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct LeaderBoardEntry {
-    pub player: String,
-    pub amount: i32,
+    pub player: String, // Entity ID of the player
+    pub shard: String,  // Shard the player is in
+    pub display_name: String,
+    pub amount: Credits,
 }
 
 impl Default for LeaderBoardEntry {
     fn default() -> Self {
         LeaderBoardEntry {
-            player: "Nobody".to_string(),
+            player: NOBODY.to_string(),
+            shard: String::new(),
+            display_name: NOBODY.to_string(),
             amount: 0,
         }
     }
 }
 
+const NOBODY: &str = "Nobody";
+const PROFILE: &str = "profile";
+const TRANSPONDER: &str = "transponder";
+const CONFIG: &str = "leaderboard";
+const PLAYER: &str = "player";
+const CREDITS: &str = "credits";
+const GLOBAL: &str = "global";
+const SHARDS_KEY: &str = "decs:leaderboard:shards";
+const MAX_PAGE_SIZE: usize = 100;
+
+/// The time windows the shard's leaderboards are kept for, besides all time
+const WINDOWS: [&str; 3] = ["daily", "weekly", "season"];
+
+/// How many seconds a shard's cached scores are trusted before being reloaded from the KV store
+const RESYNC_SECONDS: u32 = 30;
+/// Counts the resync periods that have gone by, each shard's cache remembering the one it was
+/// loaded in
+const EPOCH_KEY: &str = "decs:leaderboard:epoch";
+/// Exists for `RESYNC_SECONDS` after the epoch was last advanced
+const RESYNC_KEY: &str = "decs:leaderboard:resync";
+
+/// How long a score stays locked by an instance that died part way through tallying it, in seconds
+const SCORE_LOCK_TTL: u32 = 10;
+
+/// Every category that is ranked, the first being the current credits in the wallet
+fn categories() -> Vec<String> {
+    let mut categories = vec![
+        CREDITS.to_string(),
+        STAT_LIFETIME_CREDITS.to_string(),
+        STAT_DISTANCE.to_string(),
+        STAT_ASTEROIDS_DEPLETED.to_string(),
+    ];
+    categories.extend(STACK_TYPES.iter().map(|s| stat_mined(s)));
+    categories
+}
+
+const STACK_TYPES: [&str; 3] = ["spendy", "tasty", "critical"];
+
+/// A single leaderboard is identified by its shard and board: either a category, or one window's
+/// worth of a category such as `season:3:distance`
+type Board = (String, String);
+
+/// The top ranks of a number of collections, keyed by the collection's RID
+type Snapshot = Vec<(String, Vec<LeaderBoardEntry>)>;
+
+/// The state of one of a shard's windows: which one is running
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct Window {
+    pub number: u64,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Window { number: 1 }
+    }
+}
+
 lazy_static! {
-    static ref SCORES: RwLock<HashMap<String, HashMap<String, i32>>> = RwLock::new(HashMap::new());
+    static ref SCORES: RwLock<HashMap<Board, Ranking>> = RwLock::new(HashMap::new());
+    static ref SYNCED_AT: RwLock<HashMap<String, u64>> = RwLock::new(HashMap::new());
+    static ref NAMES: RwLock<HashMap<String, HashMap<String, String>>> =
+        RwLock::new(HashMap::new());
+    static ref CONFIGS: RwLock<HashMap<String, LeaderboardConfig>> = RwLock::new(HashMap::new());
+    static ref WINDOW_STATES: RwLock<HashMap<(String, String), Window>> =
+        RwLock::new(HashMap::new());
 }
 
 pub(crate) fn handle_frame(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> CallResult {
     let frame: decs::systemmgr::EntityFrame = serde_json::from_slice(&msg.body)?;
 
-    let wallet = get_wallet(ctx, &frame.shard, &frame.entity_id)?;
-    let old_ranks = rank_shard(SCORES.read().unwrap().get(&frame.shard));
-    put_score(&frame.shard, &frame.entity_id, wallet.credits)?;
-    let new_ranks = rank_shard(SCORES.read().unwrap().get(&frame.shard));
-    publish_changes(ctx, &frame.shard, &old_ranks, &new_ranks)?;
+    let shard = &frame.shard;
+    let wallet = get_wallet(ctx, shard, &frame.entity_id)?;
+    let name = resolve_name(ctx, shard, &frame.entity_id)?;
+    let stale = stale_shards(ctx, shard, Some(resync_epoch(ctx)?))?;
+    // Only the credits can move, unless a rename or resync changes rows in any category
+    let renamed = NAMES
+        .read()
+        .unwrap()
+        .get(shard)
+        .and_then(|n| n.get(&frame.entity_id))
+        != Some(&name);
+    let affected = if renamed || !stale.is_empty() {
+        categories()
+    } else {
+        vec![CREDITS.to_string()]
+    };
+    let old = snapshot(ctx, shard, &affected)?;
+    // Resyncing in between the two snapshots means changes made by other instances get announced too
+    for shard in stale {
+        load_shard(ctx, &shard)?;
+    }
+    roll_windows(ctx, shard)?;
+    put_score(ctx, shard, CREDITS, &frame.entity_id, wallet.credits)?;
+    put_name(shard, &frame.entity_id, name);
+    publish_changes(ctx, &old, &snapshot(ctx, shard, &affected)?)?;
+
+    Ok(vec![])
+}
+
+/// Receives an increment to a player's statistic published on `decs.stats.{shard}.{category}`
+pub(crate) fn handle_stat(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> CallResult {
+    let tokens: Vec<_> = msg.subject.split('.').collect();
+    if tokens.len() != 4 || tokens[3] == CREDITS || !categories().iter().any(|c| c == tokens[3]) {
+        return Err(format!("unknown statistic: {}", msg.subject).into());
+    }
+    let (shard, category) = (tokens[2], tokens[3]);
+    let stat: StatIncrement = serde_json::from_slice(&msg.body)?;
+
+    sync(ctx, shard)?;
+    let mut categories = vec![category.to_string()];
+    if category == STAT_LIFETIME_CREDITS {
+        // The windows' credits are the credits earned during them
+        categories.push(CREDITS.to_string());
+    }
+    let mut boards = vec![category.to_string()];
+    for window in WINDOWS.iter() {
+        boards.push(window_board(
+            window,
+            get_window(shard, window).number,
+            category,
+        ));
+    }
+    if !lock_scores(ctx, shard, &boards, &stat.entity_id)? {
+        // Nothing has been tallied, so the increment goes back to the broker to be tallied once
+        // the scores are free again. Locks expire, so this can't go on for long
+        ctx.msg().publish(&msg.subject, None, &msg.body)?;
+        return Ok(vec![]);
+    }
+    let old = snapshot(ctx, shard, &categories);
+    let tallied = old.and_then(|old| {
+        for board in boards.iter() {
+            tally(ctx, shard, board, &stat)?;
+        }
+        Ok(old)
+    });
+    for board in boards.iter() {
+        transaction::unlock(ctx, &score_lock_key(shard, board, &stat.entity_id))?;
+    }
+    let old = tallied?;
+    let named = NAMES
+        .read()
+        .unwrap()
+        .get(shard)
+        .filter(|n| n.contains_key(&stat.entity_id))
+        .is_some();
+    if !named {
+        let name = resolve_name(ctx, shard, &stat.entity_id)?;
+        put_name(shard, &stat.entity_id, name);
+    }
+    publish_changes(ctx, &old, &snapshot(ctx, shard, &categories)?)?;
 
     Ok(vec![])
 }
 
+/// Lock the player's scores on all of the given boards, making a bounded number of attempts at
+/// each. Returns whether all of them were locked; if not, none are held
+fn lock_scores(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    boards: &[String],
+    entity: &str,
+) -> std::result::Result<bool, Box<dyn std::error::Error>> {
+    for (i, board) in boards.iter().enumerate() {
+        let key = score_lock_key(shard, board, entity);
+        if transaction::lock(ctx, &key, SCORE_LOCK_TTL).is_err() {
+            for held in boards[..i].iter() {
+                transaction::unlock(ctx, &score_lock_key(shard, held, entity))?;
+            }
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Add the increment to the player's score on the board. Scores are 64-bit, which the KV store's
+/// atomic increment doesn't support, so the score is read and set again while the caller holds
+/// the score's lock, which keeps other instances from tallying the same score in between
+fn tally(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    board: &str,
+    stat: &StatIncrement,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let total = match ctx.kv().get(&score_key(shard, board, &stat.entity_id))? {
+        Some(raw) => raw.parse::<Credits>()?,
+        None => 0,
+    };
+    let total = total
+        .checked_add(stat.amount)
+        .ok_or_else(|| format!("{}'s {} score overflowed", stat.entity_id, board))?;
+    persist_score(ctx, shard, board, &stat.entity_id, total)?;
+    cache_score(shard, board, &stat.entity_id, total);
+    Ok(())
+}
+
+/// The RID of the collection for the given board. The `credits` category has no token of its own
+fn collection_rid(shard: &str, board: &str) -> String {
+    let mut rid = format!("decs.{}.leaderboard", shard);
+    for token in board.split(':').filter(|t| *t != CREDITS) {
+        rid.push('.');
+        rid.push_str(token);
+    }
+    rid
+}
+
+/// The board of a category for one window, e.g. `season:3:distance`
+fn window_board(window: &str, number: u64, category: &str) -> String {
+    format!("{}:{}:{}", window, number, category)
+}
+
+/// The collections a change in the given shard's categories can affect, each with its current ranks
+fn snapshot(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    categories: &[String],
+) -> std::result::Result<Snapshot, Box<dyn std::error::Error>> {
+    let (size, global_size) = (get_config(ctx, shard).size, get_config(ctx, GLOBAL).size);
+    let mut boards = Vec::new();
+    for category in categories {
+        boards.push((
+            collection_rid(shard, category),
+            top(ranked(shard, category, size), size),
+        ));
+        boards.push((
+            collection_rid(GLOBAL, category),
+            top(ranked(GLOBAL, category, global_size), global_size),
+        ));
+        for window in WINDOWS.iter() {
+            let board = window_board(window, get_window(shard, window).number, category);
+            boards.push((
+                collection_rid(shard, &board),
+                top(ranks(ctx, shard, &board, size)?, size),
+            ));
+        }
+    }
+    Ok(boards)
+}
+
 fn publish_changes(
+    ctx: &CapabilitiesContext,
+    old: &Snapshot,
+    new: &Snapshot,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for ((collection, old), (new_collection, new)) in old.iter().zip(new) {
+        // A window that just ended is archived as it was, and the next one starts out empty
+        if collection != new_collection {
+            continue;
+        }
+        for i in 0..old.len().min(new.len()) {
+            if old[i] != new[i] {
+                ctx.msg().publish(
+                    &format!("event.{}.{}.change", collection, i),
+                    None,
+                    &serde_json::to_vec(&json!({
+                        "values": new[i]
+                    }))?,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Make sure the shard's scores are cached, along with those of every other shard that was ever
+/// ranked so that the global leaderboard is complete
+fn sync(
     ctx: &CapabilitiesContext,
     shard: &str,
-    old: &[LeaderBoardEntry],
-    new: &[LeaderBoardEntry],
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    for i in 0..old.len() {
-        if old[i] != new[i] {
-            ctx.msg().publish(
-                &format!("event.decs.{}.leaderboard.{}.change", shard, i),
-                None,
-                &serde_json::to_vec(&json!({
-                    "values": new[i]
-                }))?,
-            )?;
+    for shard in stale_shards(ctx, shard, None)? {
+        load_shard(ctx, &shard)?;
+    }
+    Ok(())
+}
+
+/// The shards (of the given one and all those ever ranked) whose scores need loading from the KV
+/// store: those that aren't cached or, when called for a frame, were loaded before the given epoch
+fn stale_shards(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    epoch: Option<u64>,
+) -> std::result::Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut shards = ctx.kv().set_members(SHARDS_KEY)?;
+    if shard != GLOBAL && !shards.iter().any(|s| s == shard) {
+        shards.push(shard.to_string());
+    }
+    let synced_at = SYNCED_AT.read().unwrap();
+    Ok(shards
+        .into_iter()
+        .filter(|s| match (synced_at.get(s), epoch) {
+            (None, _) => true,
+            (Some(synced), Some(epoch)) => *synced != epoch,
+            (Some(_), None) => false,
+        })
+        .collect())
+}
+
+/// The current resync epoch, advancing it once `RESYNC_SECONDS` have passed since it last was.
+/// The epoch lives in the KV store rather than being derived from frame numbers, so it means the
+/// same to every shard and every instance and keeps counting across restarts. Two instances
+/// advancing it at once only causes an extra resync
+fn resync_epoch(ctx: &CapabilitiesContext) -> std::result::Result<u64, Box<dyn std::error::Error>> {
+    if ctx.kv().exists(RESYNC_KEY)? {
+        return current_epoch(ctx);
+    }
+    ctx.kv().set(RESYNC_KEY, "1", Some(RESYNC_SECONDS))?;
+    Ok(ctx.kv().atomic_add(EPOCH_KEY, 1)? as u64)
+}
+
+fn current_epoch(
+    ctx: &CapabilitiesContext,
+) -> std::result::Result<u64, Box<dyn std::error::Error>> {
+    match ctx.kv().get(EPOCH_KEY)? {
+        Some(raw) => Ok(raw.parse()?),
+        None => Ok(0),
+    }
+}
+
+fn load_shard(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().set_add(SHARDS_KEY, shard)?;
+    let mut boards = categories();
+    for window in WINDOWS.iter() {
+        let state = match load_window(ctx, shard, window)? {
+            Some(state) => state,
+            // The shard's first sync starts its first windows
+            None => {
+                let state = Window::default();
+                start_window(ctx, shard, window, &state)?;
+                state
+            }
+        };
+        boards.extend(
+            categories()
+                .iter()
+                .filter(|c| *c != CREDITS)
+                .map(|c| window_board(window, state.number, c)),
+        );
+        cache_window(shard, window, state);
+    }
+    // Boards of windows that ended since the last sync are left to the KV store
+    SCORES.write().unwrap().retain(|(s, _), _| s != shard);
+    let mut names = HashMap::new();
+    for board in boards {
+        let scores = load_scores(ctx, shard, &board)?;
+        for (player, _) in scores.iter() {
+            if !names.contains_key(player) {
+                names.insert(player.to_string(), resolve_name(ctx, shard, player)?);
+            }
         }
+        SCORES
+            .write()
+            .unwrap()
+            .insert((shard.to_string(), board), scores);
     }
+    NAMES.write().unwrap().insert(shard.to_string(), names);
+    SYNCED_AT
+        .write()
+        .unwrap()
+        .insert(shard.to_string(), current_epoch(ctx)?);
     Ok(())
 }
 
-fn put_score(
+/// Start the next of each of the shard's windows whose time is up. Only one instance gets to end a
+/// window and announces the next one through a change to the window's model. Statistics are
+/// tallied into a window as they come in, so an ended window is already archived
+fn roll_windows(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for window in WINDOWS.iter() {
+        let current = get_window(shard, window);
+        if let Some(running) = ctx.kv().get(&running_key(shard, window))? {
+            // Another instance may have started this window after this one cached the last
+            if running.parse::<u64>().ok() != Some(current.number) {
+                if let Some(state) = load_window(ctx, shard, window)? {
+                    cache_window(shard, window, state);
+                }
+            }
+            continue;
+        }
+        let claim = format!("{}:ended:{}", window_key(shard, window), current.number);
+        if ctx.kv().atomic_add(&claim, 1)? != 1 {
+            // Another instance is ending the window, pick up the next one once it's stored
+            if let Some(state) = load_window(ctx, shard, window)? {
+                cache_window(shard, window, state);
+            }
+            continue;
+        }
+        let next = Window {
+            number: current.number + 1,
+        };
+        start_window(ctx, shard, window, &next)?;
+        cache_window(shard, window, next);
+        let prefix = format!("{}:{}:", window, current.number);
+        SCORES
+            .write()
+            .unwrap()
+            .retain(|(s, b), _| s != shard || !b.starts_with(&prefix));
+        ctx.msg().publish(
+            &format!("event.decs.{}.leaderboard.{}.change", shard, window),
+            None,
+            &serde_json::to_vec(&json!({
+                "values": window_values(ctx, shard, window)
+            }))?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Store the window as the one running and time it: the window is up once its running key expires
+fn start_window(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    window: &str,
+    state: &Window,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    persist_window(ctx, shard, window, state)?;
+    let seconds = window_length(&get_config(ctx, shard), window).min(u64::from(u32::MAX));
+    ctx.kv().set(
+        &running_key(shard, window),
+        &state.number.to_string(),
+        Some(seconds as u32),
+    )?;
+    Ok(())
+}
+
+/// How many seconds the window lasts
+fn window_length(config: &LeaderboardConfig, window: &str) -> u64 {
+    match window {
+        "daily" => config.day_seconds,
+        "weekly" => config.day_seconds * 7,
+        _ => config.day_seconds * config.season_days,
+    }
+}
+
+fn window_key(shard: &str, window: &str) -> String {
+    format!("decs:{}:leaderboard:{}", shard, window)
+}
+
+fn running_key(shard: &str, window: &str) -> String {
+    format!("{}:running", window_key(shard, window))
+}
+
+fn get_window(shard: &str, window: &str) -> Window {
+    WINDOW_STATES
+        .read()
+        .unwrap()
+        .get(&(shard.to_string(), window.to_string()))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn cache_window(shard: &str, window: &str, state: Window) {
+    WINDOW_STATES
+        .write()
+        .unwrap()
+        .insert((shard.to_string(), window.to_string()), state);
+}
+
+fn load_window(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    window: &str,
+) -> std::result::Result<Option<Window>, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&window_key(shard, window))? {
+        Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        None => Ok(None),
+    }
+}
+
+fn persist_window(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    window: &str,
+    state: &Window,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().set(
+        &window_key(shard, window),
+        &serde_json::to_string(state)?,
+        None,
+    )?;
+    Ok(())
+}
+
+/// The model describing the window currently running
+fn window_values(ctx: &CapabilitiesContext, shard: &str, window: &str) -> serde_json::Value {
+    let state = get_window(shard, window);
+    json!({
+        "window": window,
+        "current": state.number,
+        "seconds": window_length(&get_config(ctx, shard), window)
+    })
+}
+
+/// Read every persisted score on one of the shard's boards. Wallets that were never scored
+/// (e.g. because they predate persistence) are scored and persisted on the way
+fn load_scores(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    category: &str,
+) -> std::result::Result<Ranking, Box<dyn std::error::Error>> {
+    let mut scores = Ranking::default();
+    for player in ctx.kv().set_members(&players_key(shard, category))? {
+        if let Some(raw) = ctx.kv().get(&score_key(shard, category, &player))? {
+            scores.put(&player, raw.parse()?);
+        }
+    }
+    if category != CREDITS {
+        return Ok(scores);
+    }
+    for entity in ctx
+        .kv()
+        .set_members(&format!("decs:{}:{}:entities", shard, super::WALLET))?
+    {
+        if scores.contains(&entity) {
+            continue;
+        }
+        if let Ok(wallet) = get_wallet(ctx, shard, &entity) {
+            persist_score(ctx, shard, CREDITS, &entity, wallet.credits)?;
+            scores.put(&entity, wallet.credits);
+        }
+    }
+    Ok(scores)
+}
+
+fn players_key(shard: &str, category: &str) -> String {
+    format!("decs:{}:leaderboard:{}:players", shard, category)
+}
+
+fn score_key(shard: &str, category: &str, entity: &str) -> String {
+    format!("decs:{}:leaderboard:{}:score:{}", shard, category, entity)
+}
+
+fn score_lock_key(shard: &str, category: &str, entity: &str) -> String {
+    format!("{}:lock", score_key(shard, category, entity))
+}
+
+fn persist_score(
+    ctx: &CapabilitiesContext,
     shard: &str,
+    category: &str,
     entity: &str,
-    amount: i32,
+    amount: Credits,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let mut scores = SCORES.write().unwrap();
-    scores.entry(shard.to_string()).or_insert_with(HashMap::new);
-    scores.entry(shard.to_string()).and_modify(|e| {
-        e.insert(entity.to_string(), amount);
-    });
+    ctx.kv().set(
+        &score_key(shard, category, entity),
+        &amount.to_string(),
+        None,
+    )?;
+    ctx.kv().set_add(&players_key(shard, category), entity)?;
+    Ok(())
+}
 
+fn put_score(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    category: &str,
+    entity: &str,
+    amount: Credits,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    persist_score(ctx, shard, category, entity, amount)?;
+    cache_score(shard, category, entity, amount);
     Ok(())
 }
 
+fn cache_score(shard: &str, category: &str, entity: &str, amount: Credits) {
+    SCORES
+        .write()
+        .unwrap()
+        .entry((shard.to_string(), category.to_string()))
+        .or_default()
+        .put(entity, amount);
+}
+
+fn put_name(shard: &str, entity: &str, name: String) {
+    NAMES
+        .write()
+        .unwrap()
+        .entry(shard.to_string())
+        .or_default()
+        .insert(entity.to_string(), name);
+}
+
+/// Determine the name a player goes by: the one in their profile, falling back to their
+/// transponder's display name and finally to their entity ID
+fn resolve_name(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<String, Box<dyn std::error::Error>> {
+    let profile = ctx
+        .kv()
+        .get(&format!("decs:components:{}:{}:{}", shard, entity, PROFILE))?
+        .and_then(|s| serde_json::from_str::<PlayerProfile>(&s).ok())
+        .map(|p| p.display_name);
+    let name = match profile {
+        Some(name) => Some(name),
+        None => ctx
+            .kv()
+            .get(&format!(
+                "decs:components:{}:{}:{}",
+                shard, entity, TRANSPONDER
+            ))?
+            .and_then(|s| serde_json::from_str::<RadarTransponder>(&s).ok())
+            .map(|t| t.display_name),
+    };
+    Ok(name
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| entity.to_string()))
+}
+
+/// The top `limit` players ranked on the board. The boards of windows that ended are read from
+/// the KV store, and a window's credits are ranked by the credits earned during it
+fn ranks(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    board: &str,
+    limit: usize,
+) -> std::result::Result<Vec<LeaderBoardEntry>, Box<dyn std::error::Error>> {
+    let (board, current) = match board.split(':').collect::<Vec<_>>().as_slice() {
+        [window, number, category] => {
+            let number = number.parse::<u64>()?;
+            let category = if *category == CREDITS {
+                STAT_LIFETIME_CREDITS
+            } else {
+                category
+            };
+            (
+                window_board(window, number, category),
+                number >= get_window(shard, window).number,
+            )
+        }
+        _ => (board.to_string(), true),
+    };
+    if !current {
+        let scores = load_scores(ctx, shard, &board)?;
+        let names = NAMES.read().unwrap();
+        return Ok(board_entries(
+            shard,
+            scores.iter().take(limit),
+            names.get(shard),
+        ));
+    }
+    Ok(ranked(shard, &board, limit))
+}
+
+/// The top `limit` players in the shard (or in all shards, for the global leaderboard) on the
+/// board. Each shard's board is already in order, so only the global leaderboard needs merging
+fn ranked(shard: &str, board: &str, limit: usize) -> Vec<LeaderBoardEntry> {
+    let scores = SCORES.read().unwrap();
+    let names = NAMES.read().unwrap();
+    if shard != GLOBAL {
+        return scores
+            .get(&(shard.to_string(), board.to_string()))
+            .map(|r| board_entries(shard, r.iter().take(limit), names.get(shard)))
+            .unwrap_or_default();
+    }
+    let entries = scores
+        .iter()
+        .filter(|((_, b), _)| b == board)
+        .flat_map(|((s, _), r)| board_entries(s, r.iter().take(limit), names.get(s)))
+        .collect();
+    let mut ranks = rank(entries);
+    ranks.truncate(limit);
+    ranks
+}
+
 fn get_wallet(
     ctx: &CapabilitiesContext,
     shard: &str,
@@ -87,77 +743,386 @@ fn get_wallet(
     }
 }
 
-// Rank all players according to their score, then return the top 10
-// if there are less than 10 players with scores, fill the remaining slots
-// with "Nobody"
-fn rank_shard(shardmap: Option<&HashMap<String, i32>>) -> Vec<LeaderBoardEntry> {
-    match shardmap {
-        Some(shardmap) => {
-            let mut entries: Vec<_> = shardmap
-                .iter()
-                .map(|(k, v)| LeaderBoardEntry {
-                    player: k.clone(),
-                    amount: *v,
-                })
-                .collect();
-            entries.sort_by(|a, b| b.amount.cmp(&a.amount));
+fn board_entries<'a>(
+    shard: &str,
+    scores: impl Iterator<Item = (&'a str, Credits)>,
+    names: Option<&HashMap<String, String>>,
+) -> Vec<LeaderBoardEntry> {
+    scores
+        .map(|(k, v)| LeaderBoardEntry {
+            player: k.to_string(),
+            shard: shard.to_string(),
+            display_name: names
+                .and_then(|n| n.get(k))
+                .cloned()
+                .unwrap_or_else(|| k.to_string()),
+            amount: v,
+        })
+        .collect()
+}
 
-            let mut leaderboard: Vec<LeaderBoardEntry> = entries.into_iter().take(10).collect();
-            for _i in 0..10 - leaderboard.len() {
-                leaderboard.push(LeaderBoardEntry::default())
-            }
-            leaderboard
+// Rank players from several shards according to their score, highest first. Ties are broken by
+// shard and entity ID so that every instance (and every page of a paged query) sees the same order
+fn rank(mut entries: Vec<LeaderBoardEntry>) -> Vec<LeaderBoardEntry> {
+    entries.sort_by(|a, b| {
+        b.amount
+            .cmp(&a.amount)
+            .then_with(|| a.shard.cmp(&b.shard))
+            .then_with(|| a.player.cmp(&b.player))
+    });
+    entries
+}
+
+// Return the top `size` ranks. If there are less than `size` players with scores,
+// fill the remaining slots with "Nobody"
+fn top(mut ranks: Vec<LeaderBoardEntry>, size: usize) -> Vec<LeaderBoardEntry> {
+    ranks.resize(size, LeaderBoardEntry::default());
+    ranks
+}
+
+/// Parses the `offset` and `limit` parameters out of a RES query string, falling back to the
+/// first page for anything missing or malformed and capping the page size
+fn parse_page(query: &str, default_limit: usize) -> (usize, usize) {
+    let mut offset = 0;
+    let mut limit = default_limit.min(MAX_PAGE_SIZE);
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next().map(str::parse::<usize>)) {
+            (Some("offset"), Some(Ok(v))) => offset = v,
+            (Some("limit"), Some(Ok(v))) if v > 0 => limit = v.min(MAX_PAGE_SIZE),
+            _ => {}
         }
-        None => std::iter::repeat(LeaderBoardEntry::default())
-            .take(10)
-            .collect(),
     }
+    (offset, limit)
 }
 
-pub(crate) fn handle_get_collection(
-    ctx: &CapabilitiesContext,
-    rid: &str,
-    msg: &messaging::BrokerMessage,
-) -> CallResult {
-    let tokens: Vec<_> = rid.split('.').collect();
-    let shard = tokens[1]; // decs.(shard).leaderboard
-
-    let ranks = rank_shard(SCORES.read().unwrap().get(shard));
+/// What a leaderboard RID refers to
+#[derive(Debug, PartialEq)]
+enum Resource {
+    Collection,
+    Rank(usize),
+    Player(String),
+    Window,
+}
 
-    let rids: Vec<_> = ranks
-        .iter()
-        .enumerate()
-        .map(|(i, _r)| ResourceIdentifier {
-            rid: format!("decs.{}.leaderboard.{}", shard, i),
-        })
-        .collect();
-    let result = json!({
-        "result": {
-            "collection": rids
+/// Splits a leaderboard RID into its shard, board and resource. The `credits` category has no
+/// token of its own, so that `decs.{shard}.leaderboard` keeps working as it always has:
+/// `decs.(shard).leaderboard[.(window).(n)][.(category)][.(idx) | .player.(entity)]`, or
+/// `decs.(shard).leaderboard.(window)` for the window currently running
+fn parse_rid(rid: &str) -> Option<(String, String, Resource)> {
+    let tokens: Vec<_> = rid.split('.').collect();
+    if tokens.len() < 3 || tokens[0] != "decs" || tokens[2] != "leaderboard" {
+        return None;
+    }
+    let shard = tokens[1].to_string();
+    let mut rest = &tokens[3..];
+    let mut window = String::new();
+    if let Some(name) = rest.first().filter(|t| WINDOWS.contains(*t)) {
+        match rest.get(1) {
+            None => return Some((shard, name.to_string(), Resource::Window)),
+            Some(number) => window = format!("{}:{}:", name, number.parse::<u64>().ok()?),
         }
-    });
-    ctx.msg()
-        .publish(&msg.reply_to, None, &serde_json::to_vec(&result)?)?;
-
-    Ok(vec![])
+        rest = &rest[2..];
+    }
+    let (category, rest) = match rest.first() {
+        Some(t) if *t != PLAYER && t.parse::<usize>().is_err() => (t.to_string(), &rest[1..]),
+        _ => (CREDITS.to_string(), rest),
+    };
+    let resource = match rest {
+        [] => Resource::Collection,
+        [idx] => Resource::Rank(idx.parse().ok()?),
+        [PLAYER, entity] => Resource::Player(entity.to_string()),
+        _ => return None,
+    };
+    Some((shard, format!("{}{}", window, category), resource))
 }
 
-pub(crate) fn handle_get_single(
+/// Serves any leaderboard resource: a collection, a single rank, a player's own rank, or the
+/// window currently running. Windows are kept per shard only
+pub(crate) fn handle_get(
     ctx: &CapabilitiesContext,
     rid: &str,
     msg: &messaging::BrokerMessage,
 ) -> CallResult {
-    let tokens: Vec<_> = rid.split('.').collect();
-    let idx: usize = tokens[3].parse()?; // decs.(shard).leaderboard.(idx)
-    let shard = tokens[1];
-    let ranks = rank_shard(SCORES.read().unwrap().get(shard));
-    let result = 
-        json!({
+    let valid = |(shard, board, resource): &(String, String, Resource)| match resource {
+        Resource::Window => shard != GLOBAL,
+        _ => {
+            let category = board.rsplit(':').next().unwrap_or_default();
+            categories().iter().any(|c| c == category) && (shard != GLOBAL || board == category)
+        }
+    };
+    let (shard, board, resource) = match parse_rid(rid) {
+        Some(parsed) if valid(&parsed) => parsed,
+        _ => return Err(format!("unknown leaderboard resource: {}", rid).into()),
+    };
+    sync(ctx, &shard)?;
+    let size = get_config(ctx, &shard).size;
+    let result = match resource {
+        Resource::Collection => get_collection(
+            &collection_rid(&shard, &board),
+            &ranks(ctx, &shard, &board, usize::MAX)?,
+            size,
+            msg,
+        ),
+        Resource::Rank(idx) => get_rank(&ranks(ctx, &shard, &board, usize::MAX)?, size, idx),
+        Resource::Player(entity) => get_player(&ranks(ctx, &shard, &board, usize::MAX)?, &entity),
+        Resource::Window => json!({
             "result": {
-                "model": ranks[idx]
+                "model": window_values(ctx, &shard, &board)
             }
-        });    
+        }),
+    };
     ctx.msg()
         .publish(&msg.reply_to, None, &serde_json::to_vec(&result)?)?;
     Ok(vec![])
 }
+
+/// The top of the leaderboard, or a page of the full ranking when queried with `offset` and `limit`.
+/// Only the top `size` ranks are live: change events are published for those alone, so a page
+/// reaching beyond them is a snapshot of the ranking at the time of the query and has to be
+/// queried again to be brought up to date
+fn get_collection(
+    collection: &str,
+    ranks: &[LeaderBoardEntry],
+    size: usize,
+    msg: &messaging::BrokerMessage,
+) -> serde_json::Value {
+    let request: serde_json::Value = serde_json::from_slice(&msg.body).unwrap_or_default();
+    match request["query"].as_str().filter(|q| !q.is_empty()) {
+        Some(query) => {
+            let (offset, limit) = parse_page(query, size);
+            let rids: Vec<_> = (offset..ranks.len().min(offset.saturating_add(limit)))
+                .map(|i| ResourceIdentifier {
+                    rid: format!("{}.{}", collection, i),
+                })
+                .collect();
+            json!({
+                "result": {
+                    "collection": rids,
+                    "query": format!("offset={}&limit={}", offset, limit)
+                }
+            })
+        }
+        None => {
+            let rids: Vec<_> = (0..size)
+                .map(|i| ResourceIdentifier {
+                    rid: format!("{}.{}", collection, i),
+                })
+                .collect();
+            json!({
+                "result": {
+                    "collection": rids
+                }
+            })
+        }
+    }
+}
+
+/// A single rank. Ranks within the leaderboard's size are always present (possibly as
+/// "Nobody") and kept up to date; ranks beyond it exist as long as there are that many players,
+/// but are snapshots that no change events are published for
+fn get_rank(ranks: &[LeaderBoardEntry], size: usize, idx: usize) -> serde_json::Value {
+    let entry = match ranks.get(idx) {
+        Some(entry) => Some(entry.clone()),
+        None if idx < size => Some(LeaderBoardEntry::default()),
+        None => None,
+    };
+    match entry {
+        Some(entry) => json!({
+            "result": {
+                "model": entry
+            }
+        }),
+        None => not_found(),
+    }
+}
+
+/// A player's own rank (1 being the top), wherever they are in the ranking. On the global
+/// leaderboard this is the player's best rank, should the same entity ID exist in several shards
+fn get_player(ranks: &[LeaderBoardEntry], entity: &str) -> serde_json::Value {
+    match ranks.iter().position(|e| e.player == entity) {
+        Some(idx) => json!({
+            "result": {
+                "model": {
+                    "rank": idx + 1,
+                    "player": ranks[idx].player,
+                    "shard": ranks[idx].shard,
+                    "display_name": ranks[idx].display_name,
+                    "amount": ranks[idx].amount
+                }
+            }
+        }),
+        None => not_found(),
+    }
+}
+
+fn not_found() -> serde_json::Value {
+    json!({
+        "error": {
+            "code": "system.notFound",
+            "message": "Not found"
+        }
+    })
+}
+
+// Retrieve the leaderboard configuration from the cache. If it's not in the cache, attempt
+// to query it from the KV store. If it's not in there, use the default configuration without
+// caching it, so a configuration that is set later on is still picked up.
+fn get_config(ctx: &CapabilitiesContext, shard: &str) -> LeaderboardConfig {
+    if let Some(config) = CONFIGS.read().unwrap().get(shard) {
+        return config.clone();
+    }
+    match load_config(ctx, shard) {
+        Ok(Some(config)) => {
+            CONFIGS
+                .write()
+                .unwrap()
+                .insert(shard.to_string(), config.clone());
+            config
+        }
+        _ => LeaderboardConfig::default(),
+    }
+}
+
+fn load_config(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+) -> std::result::Result<Option<LeaderboardConfig>, Box<dyn std::error::Error>> {
+    let key = format!("decs:components:{}:universe:{}", shard, CONFIG);
+    match ctx.kv().get(&key)? {
+        Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        board_entries, collection_rid, parse_page, parse_rid, rank, top, Ranking, Resource,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_rank() {
+        let scores: Ranking = vec![
+            ("entity2".to_string(), 50),
+            ("entity1".to_string(), 50),
+            ("entity3".to_string(), 90),
+        ]
+        .into_iter()
+        .collect();
+        let mut names = HashMap::new();
+        names.insert("entity3".to_string(), "Rapid Comet".to_string());
+
+        let ranks = top(board_entries("the_void", scores.iter(), Some(&names)), 5);
+        assert_eq!(5, ranks.len());
+        assert_eq!("Rapid Comet", ranks[0].display_name);
+        assert_eq!("entity1", ranks[1].player);
+        assert_eq!("entity2", ranks[2].display_name);
+        assert_eq!("Nobody", ranks[4].player);
+
+        // Merging shards for the global leaderboard
+        let other: Ranking = vec![("entity1".to_string(), 70)].into_iter().collect();
+        let mut entries = board_entries("the_void", scores.iter(), None);
+        entries.extend(board_entries("mainworld", other.iter(), None));
+        let global = top(rank(entries), 2);
+        assert_eq!(
+            vec![("the_void", "entity3"), ("mainworld", "entity1")],
+            global
+                .iter()
+                .map(|e| (e.shard.as_str(), e.player.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_rid() {
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "credits".to_string(),
+                Resource::Collection
+            )),
+            parse_rid("decs.the_void.leaderboard")
+        );
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "credits".to_string(),
+                Resource::Rank(12)
+            )),
+            parse_rid("decs.the_void.leaderboard.12")
+        );
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "distance".to_string(),
+                Resource::Player("entity4".to_string())
+            )),
+            parse_rid("decs.the_void.leaderboard.distance.player.entity4")
+        );
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "mined_tasty".to_string(),
+                Resource::Rank(0)
+            )),
+            parse_rid("decs.the_void.leaderboard.mined_tasty.0")
+        );
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "season:3:credits".to_string(),
+                Resource::Collection
+            )),
+            parse_rid("decs.the_void.leaderboard.season.3")
+        );
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "daily:12:distance".to_string(),
+                Resource::Rank(4)
+            )),
+            parse_rid("decs.the_void.leaderboard.daily.12.distance.4")
+        );
+        assert_eq!(
+            Some((
+                "the_void".to_string(),
+                "weekly".to_string(),
+                Resource::Window
+            )),
+            parse_rid("decs.the_void.leaderboard.weekly")
+        );
+        assert_eq!(None, parse_rid("decs.the_void.leaderboard.season.last"));
+        assert_eq!(None, parse_rid("decs.the_void.leaderboard.distance.nope"));
+        assert_eq!(None, parse_rid("decs.the_void.ledger.entity4"));
+    }
+
+    #[test]
+    fn test_collection_rid() {
+        assert_eq!(
+            "decs.the_void.leaderboard",
+            collection_rid("the_void", "credits")
+        );
+        assert_eq!(
+            "decs.global.leaderboard.distance",
+            collection_rid("global", "distance")
+        );
+        assert_eq!(
+            "decs.the_void.leaderboard.season.3",
+            collection_rid("the_void", "season:3:credits")
+        );
+        assert_eq!(
+            "decs.the_void.leaderboard.daily.12.mined_tasty",
+            collection_rid("the_void", "daily:12:mined_tasty")
+        );
+    }
+
+    #[test]
+    fn test_parse_page() {
+        assert_eq!((0, 10), parse_page("", 10));
+        assert_eq!((40, 20), parse_page("offset=40&limit=20", 10));
+        assert_eq!((0, 100), parse_page("limit=100000", 10));
+        assert_eq!((0, 10), parse_page("offset=-1&limit=0", 10));
+    }
+}
@@ -46,6 +46,8 @@ pub fn handle_call(ctx: &CapabilitiesContext, operation: &str, msg: &[u8]) -> Ca
 /// Routes message to corresponding function depending on the subject of the message
 /// `decs.system.registry` => handle_ping function for registry pings
 /// `decs.frames.{shard}.{system}` => handle_frame for updating the leaderboard
+/// `decs.stats.{shard}.{category}` => handle_stat for updating the other categories
+/// `get.decs.{shard}.leaderboard[.{category}]...` => handle_get for the leaderboards themselves
 fn handle_message(
     ctx: &CapabilitiesContext,
     msg: impl Into<messaging::DeliverMessage>,
@@ -59,12 +61,11 @@ fn handle_message(
             && msg.subject.ends_with(".shard_ldrboard")
         {
             leaderboard::handle_frame(ctx, msg)
+        } else if msg.subject.starts_with("decs.stats.") {
+            leaderboard::handle_stat(ctx, msg)
         } else {
             match ResProtocolRequest::from(msg.subject.as_str()) {
-                ResProtocolRequest::Get(rid) if msg.subject.ends_with("leaderboard") => {
-                    leaderboard::handle_get_collection(ctx, &rid, &msg)
-                }
-                ResProtocolRequest::Get(rid) => leaderboard::handle_get_single(ctx, &rid, &msg),
+                ResProtocolRequest::Get(rid) => leaderboard::handle_get(ctx, &rid, &msg),
                 ResProtocolRequest::Access(_) => handle_access(ctx, &msg),
                 _ => Err("unknown service request format".into()),
             }
@@ -108,3 +109,4 @@ fn handle_ping(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> Call
 }
 
 mod leaderboard;
+mod ranking;
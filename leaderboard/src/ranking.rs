@@ -0,0 +1,79 @@
+//! # Ranking
+//!
+//! The scores on a single leaderboard, kept in an index ordered by score so that the top ranks can
+//! be read off without sorting. Changing a player's score moves them within the index in
+//! O(log n), rather than re-ranking every player on the board.
+use stacktrader_types as trader;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::iter::FromIterator;
+use trader::components::Credits;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Ranking {
+    scores: HashMap<String, Credits>,
+    order: BTreeSet<(Reverse<Credits>, String)>, // Highest score first, ties by entity ID
+}
+
+impl Ranking {
+    /// Set a player's score, returning whether it changed
+    pub fn put(&mut self, player: &str, amount: Credits) -> bool {
+        match self.scores.insert(player.to_string(), amount) {
+            Some(old) if old == amount => false,
+            old => {
+                if let Some(old) = old {
+                    self.order.remove(&(Reverse(old), player.to_string()));
+                }
+                self.order.insert((Reverse(amount), player.to_string()));
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, player: &str) -> bool {
+        self.scores.contains_key(player)
+    }
+
+    /// Every player and their score, highest first
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Credits)> {
+        self.order.iter().map(|(Reverse(a), p)| (p.as_str(), *a))
+    }
+}
+
+impl FromIterator<(String, Credits)> for Ranking {
+    fn from_iter<I: IntoIterator<Item = (String, Credits)>>(iter: I) -> Self {
+        let mut ranking = Ranking::default();
+        for (player, amount) in iter {
+            ranking.put(&player, amount);
+        }
+        ranking
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Ranking;
+
+    fn players(ranking: &Ranking) -> Vec<&str> {
+        ranking.iter().map(|(p, _)| p).collect()
+    }
+
+    #[test]
+    fn test_put() {
+        let mut ranking: Ranking = vec![
+            ("entity1".to_string(), 50),
+            ("entity3".to_string(), 90),
+            ("entity2".to_string(), 50),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(vec!["entity3", "entity1", "entity2"], players(&ranking));
+
+        assert!(ranking.put("entity2", 100));
+        assert_eq!(vec!["entity2", "entity3", "entity1"], players(&ranking));
+        assert!(!ranking.put("entity2", 100));
+        assert!(ranking.put("entity3", 10));
+        assert_eq!(vec!["entity2", "entity1", "entity3"], players(&ranking));
+        assert_eq!(Some(("entity3", 10)), ranking.iter().last());
+    }
+}
@@ -0,0 +1,228 @@
+//! # Order Book
+//!
+//! Each shard has one order book per stack type. Bids are kept highest price first and asks
+//! lowest price first, with ties going to whichever order was placed first (price-time
+//! priority). Crossing orders trade at the price of the order that was on the book first.
+use stacktrader_types as trader;
+use trader::components::*;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub(crate) struct BookOrder {
+    pub id: String,
+    pub owner: String,     // Entity ID of the player who placed the order
+    pub order_rid: String, // RID of the order in the owner's `market_orders` collection
+    pub qty: u32,          // Units still to be bought or sold
+    pub price: Credits,
+    pub seq: i32, // Shard-wide sequence number of the order, used to break price ties
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub(crate) struct OrderBook {
+    pub bids: Vec<BookOrder>,
+    pub asks: Vec<BookOrder>,
+}
+
+/// A single trade between the best bid and the best ask. The orders are as they were
+/// immediately before the trade
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Fill {
+    pub bid: BookOrder,
+    pub ask: BookOrder,
+    pub qty: u32,
+    pub price: Credits,
+}
+
+/// A RES event needed to bring a client's copy of one side of a book up to date
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum BookEvent {
+    Add(usize, BookOrder),
+    Remove(usize),
+    Change(BookOrder),
+}
+
+impl OrderBook {
+    pub fn insert(&mut self, side: OrderSide, order: BookOrder) {
+        match side {
+            OrderSide::Buy => {
+                let idx = self
+                    .bids
+                    .iter()
+                    .position(|o| {
+                        o.price < order.price || (o.price == order.price && o.seq > order.seq)
+                    })
+                    .unwrap_or(self.bids.len());
+                self.bids.insert(idx, order);
+            }
+            OrderSide::Sell => {
+                let idx = self
+                    .asks
+                    .iter()
+                    .position(|o| {
+                        o.price > order.price || (o.price == order.price && o.seq > order.seq)
+                    })
+                    .unwrap_or(self.asks.len());
+                self.asks.insert(idx, order);
+            }
+        }
+    }
+
+    /// Take the order with the given ID off the book, returning it along with the side it was on.
+    /// Only the order's owner may take it off the book; anyone else's order is left in place
+    pub fn remove(&mut self, id: &str, owner: &str) -> Option<(OrderSide, BookOrder)> {
+        let owned = |o: &BookOrder| o.id == id && o.owner == owner;
+        if let Some(idx) = self.bids.iter().position(owned) {
+            Some((OrderSide::Buy, self.bids.remove(idx)))
+        } else if let Some(idx) = self.asks.iter().position(owned) {
+            Some((OrderSide::Sell, self.asks.remove(idx)))
+        } else {
+            None
+        }
+    }
+
+    /// Fill crossing orders until the best bid is below the best ask (or a side runs out)
+    pub fn match_orders(&mut self) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        while let (Some(bid), Some(ask)) = (self.bids.first(), self.asks.first()) {
+            if bid.price < ask.price {
+                break;
+            }
+            let qty = bid.qty.min(ask.qty);
+            let price = if bid.seq < ask.seq {
+                bid.price
+            } else {
+                ask.price
+            };
+            fills.push(Fill {
+                bid: bid.clone(),
+                ask: ask.clone(),
+                qty,
+                price,
+            });
+            self.bids[0].qty -= qty;
+            self.asks[0].qty -= qty;
+            if self.bids[0].qty == 0 {
+                self.bids.remove(0);
+            }
+            if self.asks[0].qty == 0 {
+                self.asks.remove(0);
+            }
+        }
+        fills
+    }
+}
+
+/// Compute the events that turn the `old` side of a book into the `new` one. Orders never change
+/// places relative to each other, so removing the orders that are gone (last first) and then
+/// adding the new ones (first first) always yields the right indices
+pub(crate) fn diff(old: &[BookOrder], new: &[BookOrder]) -> Vec<BookEvent> {
+    let mut events = Vec::new();
+    for (idx, order) in old.iter().enumerate().rev() {
+        if !new.iter().any(|o| o.id == order.id) {
+            events.push(BookEvent::Remove(idx));
+        }
+    }
+    for (idx, order) in new.iter().enumerate() {
+        match old.iter().find(|o| o.id == order.id) {
+            None => events.push(BookEvent::Add(idx, order.clone())),
+            Some(prev) if prev.qty != order.qty => events.push(BookEvent::Change(order.clone())),
+            Some(_) => {}
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff, BookEvent, BookOrder, OrderBook, OrderSide};
+
+    fn order(id: &str, qty: u32, price: i64, seq: i32) -> BookOrder {
+        BookOrder {
+            id: id.to_string(),
+            owner: format!("owner_{}", id),
+            order_rid: format!("decs.components.the_void.owner_{}.market_orders.{}", id, id),
+            qty,
+            price,
+            seq,
+        }
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let mut book = OrderBook::default();
+        book.insert(OrderSide::Buy, order("b1", 5, 40, 1));
+        book.insert(OrderSide::Buy, order("b2", 5, 50, 2));
+        book.insert(OrderSide::Buy, order("b3", 5, 40, 3));
+        book.insert(OrderSide::Sell, order("s1", 5, 70, 4));
+        book.insert(OrderSide::Sell, order("s2", 5, 60, 5));
+
+        let bids: Vec<_> = book.bids.iter().map(|o| o.id.as_str()).collect();
+        let asks: Vec<_> = book.asks.iter().map(|o| o.id.as_str()).collect();
+        assert_eq!(vec!["b2", "b1", "b3"], bids);
+        assert_eq!(vec!["s2", "s1"], asks);
+        assert!(book.match_orders().is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut book = OrderBook::default();
+        book.insert(OrderSide::Buy, order("b1", 5, 40, 1));
+        book.insert(OrderSide::Sell, order("s1", 5, 70, 2));
+
+        assert_eq!(None, book.remove("b1", "owner_s1"));
+        assert_eq!(1, book.bids.len());
+        let (side, removed) = book.remove("b1", "owner_b1").unwrap();
+        assert_eq!((OrderSide::Buy, "b1"), (side, removed.id.as_str()));
+        assert!(book.bids.is_empty());
+        assert_eq!(OrderSide::Sell, book.remove("s1", "owner_s1").unwrap().0);
+        assert_eq!(None, book.remove("s1", "owner_s1"));
+    }
+
+    #[test]
+    fn test_partial_fills() {
+        let mut book = OrderBook::default();
+        book.insert(OrderSide::Sell, order("s1", 4, 30, 1));
+        book.insert(OrderSide::Sell, order("s2", 10, 35, 2));
+        book.insert(OrderSide::Buy, order("b1", 6, 40, 3));
+
+        let fills = book.match_orders();
+        assert_eq!(2, fills.len());
+        // Resting asks set the price
+        assert_eq!((4, 30), (fills[0].qty, fills[0].price));
+        assert_eq!((2, 35), (fills[1].qty, fills[1].price));
+        assert!(book.bids.is_empty());
+        assert_eq!(1, book.asks.len());
+        assert_eq!(8, book.asks[0].qty);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![
+            order("a", 1, 10, 1),
+            order("b", 2, 20, 2),
+            order("c", 3, 30, 3),
+        ];
+        let new = vec![
+            order("a", 1, 10, 1),
+            order("d", 4, 25, 4),
+            order("c", 1, 30, 3),
+        ];
+
+        let events = diff(&old, &new);
+        assert_eq!(
+            vec![
+                BookEvent::Remove(1),
+                BookEvent::Add(1, order("d", 4, 25, 4)),
+                BookEvent::Change(order("c", 1, 30, 3)),
+            ],
+            events
+        );
+
+        let mut book = OrderBook::default();
+        book.insert(OrderSide::Buy, order("a", 1, 10, 1));
+        assert_eq!(
+            Some(OrderSide::Buy),
+            book.remove("a", "owner_a").map(|(side, _)| side)
+        );
+        assert_eq!(None, book.remove("a", "owner_a"));
+    }
+}
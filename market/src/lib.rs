@@ -0,0 +1,103 @@
+// Copyright 2015-2019 Capital One Services, LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[macro_use]
+extern crate serde_derive;
+
+extern crate decscloud_common as decs;
+extern crate waxosuit_guest as guest;
+
+use decs::gateway::ResProtocolRequest;
+use decs::systemmgr::*;
+use guest::prelude::*;
+
+call_handler!(handle_call);
+
+const NO_MESSAGE: &str = "(no message)";
+const MARKET_ORDERS: &str = "market_orders";
+const INVENTORY: &str = "inventory";
+const SYSTEM_NAME: &str = "market";
+const REGISTRY_SUBJECT: &str = "decs.system.registry";
+const FRAMERATE: u32 = 1;
+
+pub fn handle_call(ctx: &CapabilitiesContext, operation: &str, msg: &[u8]) -> CallResult {
+    match operation {
+        messaging::OP_DELIVER_MESSAGE => handle_message(ctx, msg),
+        core::OP_HEALTH_REQUEST => Ok(vec![]),
+        _ => Err("bad dispatch".into()),
+    }
+}
+
+/// Routes message to corresponding function depending on the subject of the message
+/// `decs.system.registry` => handle_ping function for registry pings
+/// `decs.frames.{shard}.{system}` => handle_frame for placing, cancelling and matching orders
+/// `get.decs.{shard}.market.{stack_type}.{bids|asks}[.{order}]` => handle_get for the order books
+fn handle_message(
+    ctx: &CapabilitiesContext,
+    msg: impl Into<messaging::DeliverMessage>,
+) -> CallResult {
+    let msg = msg.into().message;
+    let subject = msg
+        .as_ref()
+        .map_or(NO_MESSAGE.to_string(), |m| m.subject.to_string());
+    ctx.log(&format!(
+        "Received message from broker on subject '{}'",
+        subject
+    ));
+    match subject.as_ref() {
+        NO_MESSAGE => Err("No message".into()),
+        REGISTRY_SUBJECT => handle_ping(ctx, msg.unwrap()),
+        s if s.starts_with("decs.frames.") => market::handle_frame(ctx, msg.unwrap()),
+        s => match ResProtocolRequest::from(s) {
+            ResProtocolRequest::Get(rid) => market::handle_get(ctx, &rid, &msg.unwrap()),
+            ResProtocolRequest::Access(_) => handle_access(ctx, &msg.unwrap()),
+            _ => Err("unknown service request format".into()),
+        },
+    }
+}
+
+fn handle_access(ctx: &CapabilitiesContext, msg: &messaging::BrokerMessage) -> CallResult {
+    let result = serde_json::json!({
+        "result" : {
+            "get" : true
+        }
+    });
+    ctx.msg()
+        .publish(&msg.reply_to, None, &serde_json::to_vec(&result)?)?;
+    Ok(vec![])
+}
+
+/// Receives messages on the subject `system.registry` and replies with market system metadata
+fn handle_ping(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> CallResult {
+    let payload = System {
+        name: SYSTEM_NAME.to_string(),
+        framerate: FRAMERATE,
+        components: vec![MARKET_ORDERS.to_string()],
+    };
+    let reply_to = if msg.reply_to.is_empty() {
+        format!("{}.replies", REGISTRY_SUBJECT)
+    } else {
+        msg.reply_to
+    };
+    if let Err(e) = ctx
+        .msg()
+        .publish(&reply_to, None, &serde_json::to_vec(&payload)?)
+    {
+        return Err(format!("Error publishing message: {}", e).into());
+    };
+    Ok(vec![])
+}
+
+mod book;
+mod market;
@@ -0,0 +1,726 @@
+//! # Market
+//!
+//! The market system awaits frames for entities that have a `market_orders` collection, i.e.
+//! players who have posted buy or sell orders at a starbase. Each time it encounters such a
+//! frame, it will perform the following operations on each order in the collection:
+//! - place a `new` order on the order book for its stack type, provided the player is within
+//!   trading range of a starbase and can deliver: the item being sold is taken out of the
+//!   player's inventory, and the full price of a buy order is taken out of the player's wallet
+//! - take a `cancelled` order off the book, returning the unfilled items or credits, and delete it.
+//!   Only the player who placed an order can take it off the book
+//! - delete a `filled` order, which has already been paid out
+//!
+//! Placing or cancelling an order writes the book back to the KV store straight away, alongside
+//! the escrow, so that a failure later on in the frame can never leave escrowed items or credits
+//! without an order on the book (or the other way around). If the book can't be written, the
+//! escrow is handed back. An item can only ever be escrowed by one order: its RID is claimed as
+//! a transaction, so a second order naming it is rejected even before the item is gone from the
+//! inventory.
+//!
+//! Every change to a book is made while holding a lock on it in the KV store, to a copy of the
+//! book read afresh under that lock, so that two market systems processing frames at the same
+//! time never overwrite each other's changes.
+//!
+//! Once the orders are processed, every book that changed is matched: crossing orders are filled
+//! at the price of the order that was on the book first. Sellers are paid, buyers receive the
+//! items in their inventory (and a refund if they bid more than the fill price), and both orders
+//! are updated. Books are kept in the KV store and served as RES collections at
+//! `decs.{shard}.market.{stack_type}.bids` and `decs.{shard}.market.{stack_type}.asks`.
+use decscloud_common::gateway::*;
+use guest::prelude::*;
+use stacktrader_types as trader;
+use std::collections::BTreeSet;
+use trader::components::*;
+use trader::events::{publish_stat, STAT_LIFETIME_CREDITS};
+use trader::starbase;
+use trader::transaction::{self, Claim};
+use trader::wallet::*;
+
+use super::book::{self, BookEvent, BookOrder, OrderBook};
+
+const BIDS: &str = "bids";
+const ASKS: &str = "asks";
+const ESCROW_REASON: &str = "market escrow";
+const REFUND_REASON: &str = "market refund";
+const SALE_REASON: &str = "market sale";
+/// How long a book may stay locked by a market system that died part way through changing it, in
+/// seconds
+const BOOK_LOCK_TTL: u32 = 10;
+
+/// Receives an entity, shard, elapsed time, etc from an EntityFrame
+/// published on decs.frames.{shard}.{system}, e.g. `decs.frames.the_void.market`
+pub(crate) fn handle_frame(
+    ctx: &CapabilitiesContext,
+    msg: guest::prelude::messaging::BrokerMessage,
+) -> CallResult {
+    let subject: Vec<&str> = msg.subject.split('.').collect();
+    if subject.len() != 4 {
+        return Err("Unknown message subject received".into());
+    }
+    let frame: decs::systemmgr::EntityFrame = serde_json::from_slice(&msg.body)?;
+    let key = format!(
+        "decs:components:{}:{}:{}",
+        frame.shard,
+        frame.entity_id,
+        super::MARKET_ORDERS
+    );
+    let rids = ctx.kv().list_range(&key, 0, -1)?;
+    if rids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut session = Session::new(ctx, &frame);
+    for rid in rids {
+        let order = match get_order(ctx, &rid) {
+            Ok(order) => order,
+            Err(_) => continue, // already removed
+        };
+        match order.status {
+            OrderStatus::New => place_order(&mut session, &rid, order)?,
+            OrderStatus::Cancelled => cancel_order(&mut session, &rid, order)?,
+            // Everything owed for a filled order has been paid out, so it's only clutter now
+            OrderStatus::Filled => publish_order_delete(ctx, &frame.shard, &frame.entity_id, &rid)?,
+            OrderStatus::Open | OrderStatus::Rejected => {}
+        }
+    }
+    session.settle()?;
+
+    Ok(vec![])
+}
+
+/// Serves the bids or asks of a book as a collection, or a single order on it as a model
+pub(crate) fn handle_get(
+    ctx: &CapabilitiesContext,
+    rid: &str,
+    msg: &messaging::BrokerMessage,
+) -> CallResult {
+    // decs.(shard).market.(stack_type).(bids|asks)[.(order)]
+    let tokens: Vec<_> = rid.split('.').collect();
+    if tokens.len() < 5 || tokens.len() > 6 || (tokens[4] != BIDS && tokens[4] != ASKS) {
+        return Err(format!("unknown market resource: {}", rid).into());
+    }
+    let book = get_book(ctx, tokens[1], tokens[3])?;
+    let orders = if tokens[4] == BIDS {
+        book.bids
+    } else {
+        book.asks
+    };
+    let result = if tokens.len() == 5 {
+        let collection: Vec<_> = orders
+            .iter()
+            .map(|o| ResourceIdentifier {
+                rid: format!("{}.{}", rid, o.id),
+            })
+            .collect();
+        serde_json::json!({ "result": { "collection": collection } })
+    } else {
+        match orders.iter().find(|o| o.id == tokens[5]) {
+            Some(order) => serde_json::json!({ "result": { "model": book_model(order) } }),
+            None => serde_json::json!({
+                "error": { "code": "system.notFound", "message": "Not found" }
+            }),
+        }
+    };
+    ctx.msg()
+        .publish(&msg.reply_to, None, &serde_json::to_vec(&result)?)?;
+    Ok(vec![])
+}
+
+/// State gathered while processing a frame. Books are saved and announced whenever an order is
+/// placed or cancelled, and once more after matching
+struct Session<'a> {
+    ctx: &'a CapabilitiesContext,
+    shard: String,
+    entity: String,
+    seq_no: u64,
+    touched: BTreeSet<String>, // Stack types of the books changed during this frame
+}
+
+impl<'a> Session<'a> {
+    fn new(ctx: &'a CapabilitiesContext, frame: &decs::systemmgr::EntityFrame) -> Self {
+        Session {
+            ctx,
+            shard: frame.shard.clone(),
+            entity: frame.entity_id.clone(),
+            seq_no: frame.seq_no,
+            touched: BTreeSet::new(),
+        }
+    }
+
+    /// Make a change to a book while holding its lock. The change is made to the book as it is
+    /// stored right now, and is only written back, along with the collection events for whatever
+    /// changed, if `change` succeeds
+    fn update_book<T, F>(
+        &mut self,
+        stack_type: &str,
+        change: F,
+    ) -> std::result::Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&mut Self, &mut OrderBook) -> std::result::Result<T, Box<dyn std::error::Error>>,
+    {
+        let lock = format!("{}:lock", book_key(&self.shard, stack_type));
+        transaction::lock(self.ctx, &lock, BOOK_LOCK_TTL)?;
+        let updated = self.change_book(stack_type, change);
+        transaction::unlock(self.ctx, &lock)?;
+        updated
+    }
+
+    fn change_book<T, F>(
+        &mut self,
+        stack_type: &str,
+        change: F,
+    ) -> std::result::Result<T, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&mut Self, &mut OrderBook) -> std::result::Result<T, Box<dyn std::error::Error>>,
+    {
+        let old = get_book(self.ctx, &self.shard, stack_type)?;
+        let mut new = old.clone();
+        let changed = change(self, &mut new)?;
+        if old != new {
+            self.ctx.kv().set(
+                &book_key(&self.shard, stack_type),
+                &serde_json::to_string(&new)?,
+                None,
+            )?;
+            self.touched.insert(stack_type.to_string());
+            publish_book_events(
+                self.ctx,
+                &self.shard,
+                stack_type,
+                BIDS,
+                &old.bids,
+                &new.bids,
+            )?;
+            publish_book_events(
+                self.ctx,
+                &self.shard,
+                stack_type,
+                ASKS,
+                &old.asks,
+                &new.asks,
+            )?;
+        }
+        Ok(changed)
+    }
+
+    /// Add `amount` (which may be negative) to the entity's wallet, recording it in the ledger. A
+    /// wallet is never taken below zero
+    fn transfer(
+        &mut self,
+        entity: &str,
+        amount: Credits,
+        entry: LedgerEntry,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let wallet = update_wallet(self.ctx, &self.shard, entity, |wallet| {
+            match wallet.checked_add(amount) {
+                Some(wallet) if wallet.credits < 0 && amount < 0 => {
+                    Err("insufficient credits".into())
+                }
+                Some(wallet) => Ok(wallet),
+                None => Err("transfer would overflow the wallet".into()),
+            }
+        })?;
+        publish_ledger_entry(
+            self.ctx,
+            &self.shard,
+            entity,
+            &LedgerEntry {
+                seq_no: self.seq_no,
+                amount,
+                balance: wallet.credits,
+                ..entry
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Match every book changed during this frame, apply the fills, then write the books back
+    /// and publish the collection events for whatever changed
+    fn settle(mut self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let stack_types = std::mem::take(&mut self.touched);
+        for stack_type in stack_types {
+            self.update_book(&stack_type, |session, book| {
+                for fill in book.match_orders() {
+                    session.apply_fill(&stack_type, fill)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn apply_fill(
+        &mut self,
+        stack_type: &str,
+        fill: book::Fill,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let resource = MiningResource {
+            stack_type: stack_type.to_string(),
+            qty: fill.qty,
+        };
+        let qty = Credits::from(fill.qty);
+        let txid = format!("{}.{}", fill.bid.id, fill.ask.id);
+        // The buyer escrowed the bid price, which is never less than the fill price
+        let proceeds = fill.price.checked_mul(qty).ok_or("fill is too large")?;
+        let refund = (fill.bid.price - fill.price) * qty;
+
+        self.transfer(
+            &fill.ask.owner,
+            proceeds,
+            LedgerEntry {
+                txid: txid.clone(),
+                counterparty: fill.bid.owner.clone(),
+                item: Some(resource.clone()),
+                reason: SALE_REASON.to_string(),
+                ..Default::default()
+            },
+        )?;
+        publish_stat(
+            self.ctx,
+            &self.shard,
+            &fill.ask.owner,
+            STAT_LIFETIME_CREDITS,
+            proceeds,
+        )?;
+        if refund > 0 {
+            self.transfer(
+                &fill.bid.owner,
+                refund,
+                LedgerEntry {
+                    txid,
+                    counterparty: fill.ask.owner.clone(),
+                    item: None,
+                    reason: REFUND_REASON.to_string(),
+                    ..Default::default()
+                },
+            )?;
+        }
+        publish_inventory_new(
+            self.ctx,
+            &self.shard,
+            &fill.bid.owner,
+            &InventoryItem {
+                resource,
+                error: None,
+            },
+        )?;
+        publish_fill(self.ctx, OrderSide::Buy, stack_type, &fill.bid, fill.qty)?;
+        publish_fill(self.ctx, OrderSide::Sell, stack_type, &fill.ask, fill.qty)
+    }
+}
+
+/// Check a new order and, if it is sound, escrow what it offers and put it on the book
+fn place_order(
+    session: &mut Session,
+    rid: &str,
+    order: MarketOrder,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let ctx = session.ctx;
+    let id = order_id(rid);
+    let claim_key = format!("decs:{}:market:placed:{}", session.shard, id);
+    // Already on the book, the update marking it open just hasn't been applied yet
+    if ctx.kv().exists(&claim_key)? {
+        return Ok(());
+    }
+
+    let checked = match order.side {
+        OrderSide::Sell => check_sell(session, order.clone()),
+        OrderSide::Buy => check_buy(session, order.clone()),
+    };
+    let order = match checked {
+        Ok(order) => order,
+        Err(e) => {
+            let order = MarketOrder {
+                status: OrderStatus::Rejected,
+                error: Some(e.to_string()),
+                ..order
+            };
+            return publish_order(ctx, rid, &order);
+        }
+    };
+
+    // Only the first to increment the claim gets to place the order
+    if ctx.kv().atomic_add(&claim_key, 1)? != 1 {
+        return Ok(());
+    }
+    // Only one order gets to escrow a given item, however many name it
+    let item = match order.item {
+        Some(ref item) if order.side == OrderSide::Sell => Some(item.clone()),
+        _ => None,
+    };
+    if let Some(ref item) = item {
+        if transaction::claim(ctx, &session.shard, super::SYSTEM_NAME, item)? != Claim::Won {
+            ctx.kv().del_key(&claim_key)?;
+            let order = MarketOrder {
+                status: OrderStatus::Rejected,
+                error: Some("the item is already on offer".to_string()),
+                ..order
+            };
+            return publish_order(ctx, rid, &order);
+        }
+    }
+    let placed = BookOrder {
+        id: id.clone(),
+        owner: session.entity.clone(),
+        order_rid: rid.to_string(),
+        qty: order.qty,
+        price: order.price,
+        seq: ctx
+            .kv()
+            .atomic_add(&format!("decs:{}:market:seq", session.shard), 1)?,
+    };
+    if let Err(e) = take_escrow(session, &order, &placed) {
+        // Nothing is on the book yet, so let the next frame try placing the order again
+        if let Some(ref item) = item {
+            transaction::release(ctx, &session.shard, super::SYSTEM_NAME, item)?;
+        }
+        ctx.kv().del_key(&claim_key)?;
+        return Err(e);
+    }
+    if let Some(ref item) = item {
+        transaction::complete(ctx, &session.shard, super::SYSTEM_NAME, item)?;
+    }
+    let inserted = session.update_book(&order.stack_type, |_, book| {
+        book.insert(order.side, placed.clone());
+        Ok(())
+    });
+    if let Err(e) = inserted {
+        release_escrow(session, order.side, &order.stack_type, &placed)?;
+        ctx.kv().del_key(&claim_key)?;
+        return Err(e);
+    }
+    let order = MarketOrder {
+        id,
+        status: OrderStatus::Open,
+        error: None,
+        ..order
+    };
+    publish_order(ctx, rid, &order)
+}
+
+/// Take what a new order offers out of the player's hands: the item for a sell order, or the
+/// full price for a buy order
+fn take_escrow(
+    session: &mut Session,
+    order: &MarketOrder,
+    placed: &BookOrder,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    match order.side {
+        OrderSide::Sell => match order.item {
+            Some(ref item) => {
+                publish_inventory_delete(session.ctx, &session.shard, &placed.owner, item)
+            }
+            None => Ok(()),
+        },
+        OrderSide::Buy => session.transfer(
+            &placed.owner,
+            -(placed.price * Credits::from(placed.qty)), // checked in check_buy
+            LedgerEntry {
+                txid: placed.id.clone(),
+                counterparty: super::SYSTEM_NAME.to_string(),
+                reason: ESCROW_REASON.to_string(),
+                ..Default::default()
+            },
+        ),
+    }
+}
+
+/// Hand back whatever an order still holds in escrow: the unsold items of a sell order, or the
+/// unspent credits of a buy order
+fn release_escrow(
+    session: &mut Session,
+    side: OrderSide,
+    stack_type: &str,
+    placed: &BookOrder,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    match side {
+        OrderSide::Sell => publish_inventory_new(
+            session.ctx,
+            &session.shard,
+            &placed.owner,
+            &InventoryItem {
+                resource: MiningResource {
+                    stack_type: stack_type.to_string(),
+                    qty: placed.qty,
+                },
+                error: None,
+            },
+        ),
+        OrderSide::Buy => session.transfer(
+            &placed.owner,
+            placed.price * Credits::from(placed.qty),
+            LedgerEntry {
+                txid: placed.id.clone(),
+                counterparty: super::SYSTEM_NAME.to_string(),
+                reason: REFUND_REASON.to_string(),
+                ..Default::default()
+            },
+        ),
+    }
+}
+
+/// A sell order must offer an item from the player's inventory, which determines what is sold
+fn check_sell(
+    session: &mut Session,
+    order: MarketOrder,
+) -> std::result::Result<MarketOrder, Box<dyn std::error::Error>> {
+    check_common(session, &order)?;
+    let item_rid = order.item.clone().ok_or("a sell order must name an item")?;
+    let inventory = session.ctx.kv().list_range(
+        &format!(
+            "decs:components:{}:{}:{}",
+            session.shard,
+            session.entity,
+            super::INVENTORY
+        ),
+        0,
+        -1,
+    )?;
+    let raw = if inventory.contains(&item_rid) {
+        session.ctx.kv().get(&item_rid.replace('.', ":"))?
+    } else {
+        None
+    };
+    let item: InventoryItem = match raw {
+        Some(s) => serde_json::from_str(&s)?,
+        None => return Err("the item is no longer in the inventory".into()),
+    };
+    if item.resource.qty == 0 {
+        return Err("the item is empty".into());
+    }
+    Ok(MarketOrder {
+        stack_type: item.resource.stack_type,
+        qty: item.resource.qty,
+        ..order
+    })
+}
+
+/// A buy order must be for a positive quantity the player can pay for in full up front
+fn check_buy(
+    session: &mut Session,
+    order: MarketOrder,
+) -> std::result::Result<MarketOrder, Box<dyn std::error::Error>> {
+    check_common(session, &order)?;
+    if order.stack_type.is_empty() || order.qty == 0 {
+        return Err("a buy order must name a stack type and quantity".into());
+    }
+    let cost = order
+        .price
+        .checked_mul(Credits::from(order.qty))
+        .ok_or("the order is too large")?;
+    if get_wallet(session.ctx, &session.shard, &session.entity)?.credits < cost {
+        return Err("insufficient credits".into());
+    }
+    Ok(order)
+}
+
+fn check_common(
+    session: &Session,
+    order: &MarketOrder,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if order.price <= 0 {
+        return Err("the price must be positive".into());
+    }
+    if starbase::docked_at(session.ctx, &session.shard, &session.entity)?.is_none() {
+        return Err("not within range of a starbase".into());
+    }
+    Ok(())
+}
+
+/// Take an order off the book, hand back whatever it still held in escrow, and delete it
+fn cancel_order(
+    session: &mut Session,
+    rid: &str,
+    order: MarketOrder,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let ctx = session.ctx;
+    if order.id.is_empty() {
+        let claim_key = format!("decs:{}:market:placed:{}", session.shard, order_id(rid));
+        // Placed, but not yet marked open; the player will see it open and can cancel again
+        if ctx.kv().exists(&claim_key)? {
+            return Ok(());
+        }
+    } else {
+        // Only ever takes the player's own order off the book, whatever ID they cancel
+        let owner = session.entity.clone();
+        let removed = session.update_book(&order.stack_type, |_, book| {
+            Ok(book.remove(&order.id, &owner))
+        })?;
+        if let Some((side, placed)) = removed {
+            release_escrow(session, side, &order.stack_type, &placed)?;
+        }
+    }
+    // Deleting an order that is already gone is harmless, so this is safe to repeat on retries
+    publish_order_delete(ctx, &session.shard, &session.entity, rid)
+}
+
+/// Orders are identified by the last token of their RID, which the component manager
+/// guarantees to be unique
+fn order_id(rid: &str) -> String {
+    rid.rsplit('.').next().unwrap_or(rid).to_string()
+}
+
+fn book_key(shard: &str, stack_type: &str) -> String {
+    format!("decs:{}:market:{}", shard, stack_type)
+}
+
+/// The order as shown on the book. Which player posted it is public, where they keep it is not
+fn book_model(order: &BookOrder) -> serde_json::Value {
+    serde_json::json!({
+        "owner": order.owner,
+        "qty": order.qty,
+        "price": order.price,
+    })
+}
+
+fn get_book(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    stack_type: &str,
+) -> std::result::Result<OrderBook, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&book_key(shard, stack_type))? {
+        Some(s) => Ok(serde_json::from_str(&s)?),
+        None => Ok(OrderBook::default()),
+    }
+}
+
+fn get_order(
+    ctx: &CapabilitiesContext,
+    rid: &str,
+) -> std::result::Result<MarketOrder, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&rid.replace('.', ":"))? {
+        Some(s) => Ok(serde_json::from_str(&s)?),
+        None => Err("no such order".into()),
+    }
+}
+
+/// Publish the RES events that bring subscribers of one side of a book up to date
+fn publish_book_events(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    stack_type: &str,
+    side: &str,
+    old: &[BookOrder],
+    new: &[BookOrder],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let collection = format!("decs.{}.market.{}.{}", shard, stack_type, side);
+    for event in book::diff(old, new) {
+        let (subject, payload) = match event {
+            BookEvent::Add(idx, order) => (
+                format!("event.{}.add", collection),
+                serde_json::json!({
+                    "value": { "rid": format!("{}.{}", collection, order.id) },
+                    "idx": idx
+                }),
+            ),
+            BookEvent::Remove(idx) => (
+                format!("event.{}.remove", collection),
+                serde_json::json!({ "idx": idx }),
+            ),
+            BookEvent::Change(order) => (
+                format!("event.{}.{}.change", collection, order.id),
+                serde_json::json!({ "values": { "qty": order.qty } }),
+            ),
+        };
+        ctx.msg()
+            .publish(&subject, None, &serde_json::to_vec(&payload)?)?;
+    }
+    Ok(())
+}
+
+/// Update an order on the book after `qty` units of it were filled. The order is rebuilt from
+/// the book, which is authoritative for the remaining quantity
+fn publish_fill(
+    ctx: &CapabilitiesContext,
+    side: OrderSide,
+    stack_type: &str,
+    placed: &BookOrder,
+    qty: u32,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let remaining = placed.qty - qty;
+    let order = MarketOrder {
+        id: placed.id.clone(),
+        side,
+        stack_type: stack_type.to_string(),
+        qty: remaining,
+        price: placed.price,
+        item: None,
+        status: if remaining == 0 {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::Open
+        },
+        error: None,
+    };
+    publish_order(ctx, &placed.order_rid, &order)
+}
+
+fn publish_order(
+    ctx: &CapabilitiesContext,
+    rid: &str,
+    order: &MarketOrder,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let setreq = ResProtocolRequest::Set(rid.to_string());
+    let payload = serde_json::json!({ "params": order });
+    ctx.msg()
+        .publish(&setreq.to_string(), None, &serde_json::to_vec(&payload)?)?;
+    Ok(())
+}
+
+fn publish_order_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    rid: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    publish_collection_delete(ctx, shard, entity, super::MARKET_ORDERS, rid)
+}
+
+fn publish_inventory_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    rid: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    publish_collection_delete(ctx, shard, entity, super::INVENTORY, rid)
+}
+
+/// Remove a single item from one of the entity's collection components
+fn publish_collection_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    component: &str,
+    rid: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let delreq = ResProtocolRequest::Delete(format!(
+        "decs.components.{}.{}.{}",
+        shard, entity, component
+    ));
+    ctx.msg().publish(
+        &delreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({"params": {"rid": rid}}))?,
+    )?;
+    Ok(())
+}
+
+fn publish_inventory_new(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    item: &InventoryItem,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let newreq = ResProtocolRequest::New(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::INVENTORY
+    ));
+    ctx.msg().publish(
+        &newreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({ "params": item }))?,
+    )?;
+    Ok(())
+}
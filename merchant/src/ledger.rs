@@ -0,0 +1,121 @@
+//! # Ledger
+//!
+//! Every credit or debit applied to a `wallet` is appended to the entity's `ledger` collection
+//! component. Ledgers only ever grow, so rather than having the UI fetch an entire collection this
+//! module serves a paged, newest-first view of it as the RES query resource
+//! `decs.{shard}.ledger.{entity}?offset={offset}&limit={limit}`. Each item in the resulting
+//! collection links to the ledger entry held by the component manager.
+//!
+//! A ledger is private to its owner. Access is only granted to connections whose RES token
+//! carries the owning entity, i.e. a token of the form `{"entity_id": "(entity)"}` set on the
+//! connection by whichever service authenticates players.
+use decs::gateway::*;
+use guest::prelude::*;
+
+const DEFAULT_PAGE_SIZE: isize = 25;
+const MAX_PAGE_SIZE: isize = 100;
+
+/// Responds to `get.decs.{shard}.ledger.{entity}` with one page of the entity's ledger
+pub(crate) fn handle_get_history(
+    ctx: &CapabilitiesContext,
+    rid: &str,
+    msg: &messaging::BrokerMessage,
+) -> CallResult {
+    let tokens: Vec<_> = rid.split('.').collect();
+    if tokens.len() != 4 {
+        return Err(format!("unknown ledger resource: {}", rid).into());
+    }
+    let shard = tokens[1]; // decs.(shard).ledger.(entity)
+    let entity = tokens[3];
+
+    let request: serde_json::Value = serde_json::from_slice(&msg.body).unwrap_or_default();
+    let (offset, limit) = parse_page(request["query"].as_str().unwrap_or(""));
+
+    // Entries are appended to the end of the list, so walk it backwards for newest-first paging
+    let key = format!("decs:components:{}:{}:{}", shard, entity, super::LEDGER);
+    let mut rids = ctx
+        .kv()
+        .list_range(&key, -(offset + limit), -(offset + 1))?;
+    rids.reverse();
+
+    let collection: Vec<_> = rids
+        .into_iter()
+        .map(|rid| ResourceIdentifier { rid })
+        .collect();
+    let result = serde_json::json!({
+        "result": {
+            "collection": collection,
+            "query": format!("offset={}&limit={}", offset, limit)
+        }
+    });
+    ctx.msg()
+        .publish(&msg.reply_to, None, &serde_json::to_vec(&result)?)?;
+    Ok(vec![])
+}
+
+/// Responds to `access.decs.{shard}.ledger.{entity}`, only allowing the entity itself to get it
+pub(crate) fn handle_access(
+    ctx: &CapabilitiesContext,
+    rid: &str,
+    msg: &messaging::BrokerMessage,
+) -> CallResult {
+    let request: serde_json::Value = serde_json::from_slice(&msg.body).unwrap_or_default();
+    let result = serde_json::json!({
+        "result": {
+            "get": is_owner(rid, &request["token"])
+        }
+    });
+    ctx.msg()
+        .publish(&msg.reply_to, None, &serde_json::to_vec(&result)?)?;
+    Ok(vec![])
+}
+
+/// Determines whether the connection token belongs to the entity whose ledger is being accessed
+fn is_owner(rid: &str, token: &serde_json::Value) -> bool {
+    let tokens: Vec<_> = rid.split('.').collect();
+    match (tokens.as_slice(), token["entity_id"].as_str()) {
+        ([_, _, _, entity], Some(owner)) => *entity == owner,
+        _ => false,
+    }
+}
+
+/// Parses the `offset` and `limit` parameters out of a RES query string, falling back to the
+/// first page for anything missing or malformed and capping the page size
+fn parse_page(query: &str) -> (isize, isize) {
+    let mut offset = 0;
+    let mut limit = DEFAULT_PAGE_SIZE;
+    for pair in query.split('&') {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next().map(str::parse::<isize>)) {
+            (Some("offset"), Some(Ok(v))) if v >= 0 => offset = v,
+            (Some("limit"), Some(Ok(v))) if v > 0 => limit = v.min(MAX_PAGE_SIZE),
+            _ => {}
+        }
+    }
+    (offset, limit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_owner, parse_page};
+
+    #[test]
+    fn test_parse_page() {
+        assert_eq!((0, 25), parse_page(""));
+        assert_eq!((50, 10), parse_page("offset=50&limit=10"));
+        assert_eq!((0, 100), parse_page("limit=5000"));
+        assert_eq!((0, 25), parse_page("offset=-3&limit=0&sort=desc"));
+    }
+
+    #[test]
+    fn test_is_owner() {
+        let rid = "decs.the_void.ledger.bob";
+        assert!(is_owner(rid, &serde_json::json!({"entity_id": "bob"})));
+        assert!(!is_owner(rid, &serde_json::json!({"entity_id": "alice"})));
+        assert!(!is_owner(rid, &serde_json::Value::Null));
+        assert!(!is_owner(
+            "decs.the_void.ledger",
+            &serde_json::json!({"entity_id": "bob"})
+        ));
+    }
+}
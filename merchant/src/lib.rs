@@ -15,6 +15,7 @@
 extern crate decscloud_common as decs;
 extern crate waxosuit_guest as guest;
 
+use decs::gateway::ResProtocolRequest;
 use decs::systemmgr::*;
 use guest::prelude::*;
 
@@ -22,8 +23,9 @@ call_handler!(handle_call);
 
 const NO_MESSAGE: &str = "(no message)";
 const SELL_LIST: &str = "sell_list";
+const INVENTORY: &str = "inventory";
+const LEDGER: &str = "ledger";
 const SYSTEM_NAME: &str = "merchant";
-const WALLET: &str = "wallet";
 const REGISTRY_SUBJECT: &str = "decs.system.registry";
 const FRAMERATE: u32 = 1;
 
@@ -35,7 +37,10 @@ pub fn handle_call(ctx: &CapabilitiesContext, operation: &str, msg: &[u8]) -> Ca
     }
 }
 
-/// Routes message either to the `handle_ping` function for registry pings or `handle_frame` for position updates
+/// Routes message to corresponding function depending on the subject of the message
+/// `decs.system.registry` => handle_ping function for registry pings
+/// `decs.frames.{shard}.{system}` => handle_frame for processing an entity's sell list
+/// `get.decs.{shard}.ledger.{entity}` => handle_get_history for paging through an entity's ledger
 fn handle_message(
     ctx: &CapabilitiesContext,
     msg: impl Into<messaging::DeliverMessage>,
@@ -51,7 +56,12 @@ fn handle_message(
     match subject.as_ref() {
         NO_MESSAGE => Err("No message".into()),
         REGISTRY_SUBJECT => handle_ping(ctx, msg.unwrap()),
-        _ => merchant::handle_frame(ctx, msg.unwrap()),
+        s if s.starts_with("decs.frames.") => merchant::handle_frame(ctx, msg.unwrap()),
+        s => match ResProtocolRequest::from(s) {
+            ResProtocolRequest::Get(rid) => ledger::handle_get_history(ctx, &rid, &msg.unwrap()),
+            ResProtocolRequest::Access(rid) => ledger::handle_access(ctx, &rid, &msg.unwrap()),
+            _ => Err("unknown service request format".into()),
+        },
     }
 }
 
@@ -76,4 +86,5 @@ fn handle_ping(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> Call
     Ok(vec![])
 }
 
+mod ledger;
 mod merchant;
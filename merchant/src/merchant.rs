@@ -3,8 +3,15 @@
 //! The merchant system awaits frames for entities that have a `sell_list` component. Each time
 //! it encounters such a frame, it will perform the following operations on each item in the
 //! sell list:
+//! - make sure the entity is within trading range of a starbase, otherwise return the item to the
+//!   entity's `inventory` along with the reason it couldn't be sold
+//! - appraise the item using the nearest starbase's prices and determine a new amount for credits
+//! - claim the sale's transaction ID so that no other merchant (or retried frame) applies it
+//!   concurrently, publish a new `wallet` component for the entity, and only then mark the
+//!   transaction as completed. If the wallet can't be published, the claim is released and the
+//!   sale is retried on a later frame. Claims and completion markers expire from the KV store
+//! - post an entry to the entity's `ledger` collection so that every credit can be audited
 //! - delete the item from the sell list collection
-//! - appraise the item, determine a new amount for credits, and publish a new `wallet` component for the entity
 //!
 //! NOTE: the merchant system does NOT manage the player's inventory. It is the front-end's responsibility
 //! to move an item out of `inventory` and into the `sell_list` as a means of triggering the merchant
@@ -15,10 +22,17 @@ use decscloud_common::gateway::*;
 use guest::prelude::*;
 use stacktrader_types as trader;
 use trader::components::*;
+use trader::events::*;
+use trader::starbase;
+use trader::transaction::{self, Claim};
+use trader::wallet::*;
 
 const STACK_SPENDY: &str = "spendy";
 const STACK_TASTY: &str = "tasty";
 const STACK_CRITICAL: &str = "critical";
+const SALE_REASON: &str = "sale";
+/// The step of a sale that credits the wallet
+const CREDITED: &str = "credited";
 
 /// Receives an entity, shard, elapsed time, etc from an EntityFrame
 /// published on decs.frames.{shard}.{system}, e.g. `decs.frames.the_void.physics`
@@ -33,39 +47,191 @@ pub(crate) fn handle_frame(
     }
     let frame: decs::systemmgr::EntityFrame = serde_json::from_slice(&msg.body)?;
     let sell_rids = get_sell_list_rids(ctx, &frame.shard, &frame.entity_id)?;
+    if sell_rids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let post = starbase::docked_at(ctx, &frame.shard, &frame.entity_id)?;
+    let (shard, entity) = (&frame.shard, &frame.entity_id);
     for rid in sell_rids {
-        let sell_item = get_sell_item(ctx, &rid)?;
-        // NOTE: this is not transactional and we're okay with that (for now)
+        let sell_item = match get_sell_item(ctx, &rid) {
+            Ok(item) => item,
+            Err(_) => continue, // already sold and removed by another merchant
+        };
+        if let Some(reason) = sell_item.error.clone() {
+            // Rejected before rejected items were handed back, so hand it back now
+            return_item(ctx, shard, entity, &rid, sell_item, &reason)?;
+            continue;
+        }
+        let post = match post {
+            Some(ref post) => post,
+            None => {
+                let reason = "not within range of a starbase";
+                return_item(ctx, shard, entity, &rid, sell_item, reason)?;
+                continue;
+            }
+        };
+        let wallet = get_wallet(ctx, shard, entity)?;
+        let sale = appraise(&sell_item.resource, &post.starbase)
+            .filter(|amount| wallet.checked_add(*amount).is_some());
+        let amount = match sale {
+            Some(amount) => amount,
+            None => {
+                let reason = "sale would overflow the wallet";
+                return_item(ctx, shard, entity, &rid, sell_item, reason)?;
+                continue;
+            }
+        };
+        let txid = transaction_id(&frame.entity_id, &rid);
+        match transaction::claim(ctx, shard, super::SYSTEM_NAME, &txid)? {
+            Claim::Won => {
+                let sale = LedgerEntry {
+                    txid: txid.clone(),
+                    seq_no: frame.seq_no,
+                    amount,
+                    balance: 0,
+                    counterparty: post.entity_id.clone(),
+                    item: Some(sell_item.resource),
+                    reason: SALE_REASON.to_string(),
+                    ..Default::default()
+                };
+                if let Err(e) = credit_sale(ctx, shard, entity, sale) {
+                    // Let the next frame carry on with the sale from wherever this attempt stopped
+                    transaction::release(ctx, shard, super::SYSTEM_NAME, &txid)?;
+                    return Err(e);
+                }
+                publish_stat(ctx, shard, entity, STAT_LIFETIME_CREDITS, amount)?;
+            }
+            Claim::Completed => {
+                ctx.log(&format!(
+                    "Sale {} was already applied, only removing the item",
+                    txid
+                ));
+            }
+            Claim::InFlight => continue, // another merchant is part way through this sale
+        }
+        // Deleting an item that is already gone is harmless, so this is safe to repeat on retries
         publish_item_delete(ctx, &frame.shard, &frame.entity_id, &rid)?;
-        publish_credits_add(ctx, &frame.shard, &frame.entity_id, &sell_item)?;
     }
 
     Ok(vec![])
 }
 
+/// Credit a sale to the entity's wallet and record it in the ledger, and only then complete the
+/// transaction. The credit is recorded as a step of the transaction along with the balance it
+/// left, so an attempt that fails after crediting is retried without paying twice, and the
+/// ledger entry is never lost
+fn credit_sale(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    sale: LedgerEntry,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let txid = sale.txid.clone();
+    let balance = match transaction::step(ctx, shard, super::SYSTEM_NAME, &txid, CREDITED)? {
+        Some(balance) => balance.parse()?,
+        None => {
+            let wallet = update_wallet(ctx, shard, entity, |wallet| {
+                Ok(wallet
+                    .checked_add(sale.amount)
+                    .ok_or("sale would overflow the wallet")?)
+            })?;
+            let balance = wallet.credits;
+            transaction::record_step(
+                ctx,
+                shard,
+                super::SYSTEM_NAME,
+                &txid,
+                CREDITED,
+                &balance.to_string(),
+            )?;
+            balance
+        }
+    };
+    publish_ledger_entry(ctx, shard, entity, &LedgerEntry { balance, ..sale })?;
+    transaction::complete(ctx, shard, super::SYSTEM_NAME, &txid)
+}
+
+/// Produce the transaction ID for selling the given sell list item. The ID is derived from the
+/// item's RID, which the component manager guarantees to be unique, so a retried frame or a
+/// second merchant instance will always compute the same ID for the same sale
+fn transaction_id(entity: &str, rid: &str) -> String {
+    let item_id = rid.rsplit('.').next().unwrap_or(rid);
+    format!("{}.{}", entity, item_id)
+}
+
 /// Retrieve all of the fully-qualified RIDs currently in the entity's `sell_list` component
 fn get_sell_list_rids(ctx: &CapabilitiesContext, shard: &str, entity: &str) -> Result<Vec<String>> {
     let key = format!("decs:components:{}:{}:{}", shard, entity, super::SELL_LIST);
     Ok(ctx.kv().list_range(&key, 0, -1)?)
 }
 
-/// Retrieve the contents of an inventory item from the KV store. Note that due to current
-/// game design, this value is identical to a `MiningResource`. In the future, there might be
-/// another structure for an inventory item
+/// Retrieve the contents of an inventory item from the KV store
 fn get_sell_item(
     ctx: &CapabilitiesContext,
     rid: &str,
-) -> std::result::Result<MiningResource, Box<dyn std::error::Error>> {
+) -> std::result::Result<InventoryItem, Box<dyn std::error::Error>> {
     let key = rid.replace('.', ":");
     match &ctx.kv().get(&key)? {
         Some(ref s) => {
-            let mr: MiningResource = serde_json::from_str(s)?;
-            Ok(mr)
+            let item: InventoryItem = serde_json::from_str(s)?;
+            Ok(item)
         }
         None => Err("no such item".into()),
     }
 }
 
+/// Move a sell list item the merchant refused back into the entity's inventory, along with the
+/// reason the sale was refused. Returning the item is claimed just like a sale, because the item
+/// can only ever be sold or returned, so a retried frame never returns it twice
+fn return_item(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    rid: &str,
+    item: InventoryItem,
+    reason: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let txid = transaction_id(entity, rid);
+    match transaction::claim(ctx, shard, super::SYSTEM_NAME, &txid)? {
+        Claim::Won => {
+            let returned = publish_item_returned(ctx, shard, entity, item, reason)
+                .and_then(|_| transaction::complete(ctx, shard, super::SYSTEM_NAME, &txid));
+            if let Err(e) = returned {
+                transaction::release(ctx, shard, super::SYSTEM_NAME, &txid)?;
+                return Err(e);
+            }
+        }
+        Claim::Completed => {}
+        Claim::InFlight => return Ok(()),
+    }
+    publish_item_delete(ctx, shard, entity, rid)
+}
+
+/// Add the refused item to the entity's `inventory`, attaching the reason so the UI can show it
+fn publish_item_returned(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    item: InventoryItem,
+    reason: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let item = InventoryItem {
+        error: Some(reason.to_string()),
+        ..item
+    };
+    let newreq = ResProtocolRequest::New(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::INVENTORY
+    ));
+    let payload = serde_json::json!({ "params": item });
+    ctx.msg()
+        .publish(&newreq.to_string(), None, &serde_json::to_vec(&payload)?)?;
+    Ok(())
+}
+
 /// Publish a delete call for the given collection rid e.g. `decs.components.(shard).(entity).sell_list`
 /// Passing the "rid" of the item from that collection to delete will remove just that one item
 /// from the collection, per component manager protocol. In other words, component manager knows whether
@@ -88,25 +254,10 @@ fn publish_item_delete(
     Ok(())
 }
 
-/// Pull the current credits owned by the given shard+entity and produce a new wallet
-/// with that amount plus the value of the inventory item being examined. Publish that
-/// new wallet via "component set" operation targeted at the component manager.
-fn publish_credits_add(
-    ctx: &CapabilitiesContext,
-    shard: &str,
-    entity: &str,
-    item: &MiningResource,
-) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let key = format!("decs:components:{}:{}:{}", shard, entity, super::WALLET);
-
-    let wallet: CreditWallet = {
-        match ctx.kv().get(&key)? {
-            Some(s) => serde_json::from_str(&s)?,
-            None => CreditWallet::default(),
-        }
-    };
-
-    let itemval: i32 = if item.stack_type == STACK_CRITICAL {
+/// Determine the value in credits of an inventory item at the given starbase, or `None` if it is
+/// too valuable to represent
+fn appraise(item: &MiningResource, starbase: &Starbase) -> Option<Credits> {
+    let baseval: Credits = if item.stack_type == STACK_CRITICAL {
         100
     } else if item.stack_type == STACK_TASTY {
         50
@@ -115,14 +266,7 @@ fn publish_credits_add(
     } else {
         0 // this shouldn't happen unless there's a malformed mining resource in the player's inv
     };
-    let new_amount = (itemval * item.qty as i32) + wallet.credits; // TODO: this is not idempotent and potentially problematic with multiple merchant systems running...
-    let wallet = serde_json::json!({"params": CreditWallet {
-        credits: new_amount,
-    }});
-
-    let setreq = ResProtocolRequest::Set(key.replace(':', ".").to_string());
-    ctx.msg()
-        .publish(&setreq.to_string(), None, &serde_json::to_vec(&wallet)?)?;
-
-    Ok(())
+    // Float to integer casts saturate, so an absurd multiplier still ends up rejected below
+    let itemval = (baseval as f64 * starbase.price_multiplier(&item.stack_type).max(0.0)).round();
+    (itemval as Credits).checked_mul(Credits::from(item.qty))
 }
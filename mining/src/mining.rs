@@ -1,6 +1,7 @@
 use guest::prelude::*;
 use stacktrader_types as trader;
 use trader::components::*;
+use trader::events::*;
 
 const DEPLETED_COLOR: &str = "#A9A9A9";
 
@@ -87,6 +88,13 @@ fn extract_resource(
         // Take the resource item as-is from the mining resource and add to player inventory
         ctx.msg()
             .publish(&inv_subject, None, &serde_json::to_vec(&add_payload)?)?;
+        publish_stat(
+            ctx,
+            shard,
+            entity_id,
+            &stat_mined(&mining_resource.stack_type),
+            i64::from(mining_resource.qty),
+        )?;
         // The extractor target must always be the fully qualified ID of the mining_resource component
         let del_subject = format!("call.{}.delete", extractor.target);
         let params = json!({
@@ -143,6 +151,7 @@ fn extract_resource(
             None,
             &serde_json::to_vec(&json!({ "params": new_tp }))?,
         )?;
+        publish_stat(ctx, shard, entity_id, STAT_ASTEROIDS_DEPLETED, 1)?;
 
         Ok(vec![])
     } else {
@@ -172,5 +181,6 @@ fn deplete_transponder(old_tp: &RadarTransponder) -> RadarTransponder {
         color: DEPLETED_COLOR.to_string(),
         display_name: format!("{} (depleted)", old_tp.display_name),
         object_type: old_tp.object_type.clone(),
+        active: old_tp.active,
     }
 }
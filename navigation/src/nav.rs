@@ -1,6 +1,7 @@
 use guest::prelude::*;
 use stacktrader_types as trader;
 use trader::components::*;
+use trader::contacts::resolve_target;
 
 const THRESHOLD_DISTANCE_KM: f64 = 1.5;
 
@@ -69,7 +70,7 @@ fn process_frame(
     vel: &Velocity,
     target: &Target,
 ) -> CallResult {
-    let target_pos = get_target_position(ctx, &target.rid)?;
+    let target_pos = get_target_position(ctx, &entity_id, &target.rid)?;
 
     let nt = Target {
         eta_ms: pos.eta_at(&target_pos, &vel),
@@ -102,13 +103,17 @@ fn process_frame(
     Ok(vec![])
 }
 
+/// Where the target is. Contacts radar hasn't identified are targeted through a handle, which is
+/// resolved to the entity here so the player never learns which entity it is
 fn get_target_position(
     ctx: &CapabilitiesContext,
+    entity_id: &str,
     rid: &str,
 ) -> std::result::Result<Position, Box<dyn std::error::Error>> {
-    let sp: Vec<&str> = rid.split('.').collect();
-    let shard = sp[2];
-    let entity = sp[3];
+    let (shard, entity) = match resolve_target(ctx, entity_id, rid)? {
+        Some(target) => target,
+        None => return Err(format!("no such target: {}", rid).into()),
+    };
     let pos_value = ctx
         .kv()
         .get(&format!("decs:components:{}:{}:position", shard, entity))?;
@@ -25,6 +25,7 @@ use stacktrader_types as trader;
 use std::collections::HashMap;
 use std::sync::RwLock;
 use trader::components::*;
+use trader::events::*;
 
 lazy_static! {
     static ref UNIVERSE_METADATA: RwLock<HashMap<String, UniverseMetadata>> =
@@ -147,6 +148,17 @@ fn handle_frame(
                 {
                     return Err("Error publishing message".into());
                 };
+                // Distance travelled is counted in whole meters for the leaderboard
+                let meters = (position.distance_to_3d(&new_position) * 1000.0).round();
+                if meters >= 1.0 {
+                    publish_stat(
+                        ctx,
+                        &frame.shard,
+                        &frame.entity_id,
+                        STAT_DISTANCE,
+                        meters as i64,
+                    )?;
+                }
             }
         };
     } else {
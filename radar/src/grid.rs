@@ -0,0 +1,136 @@
+//! # Spatial Grid
+//!
+//! A uniform grid over a shard's space, used to find the entities near a radar receiver without
+//! visiting every entity in the shard. Each entity is filed under the cube-shaped cell containing
+//! its position, so a query only visits the cells that overlap the sphere it covers.
+use stacktrader_types as trader;
+use std::collections::{HashMap, HashSet};
+use trader::components::Position;
+
+/// Edge length of a cell in km, in the order of a typical radar receiver's radius
+const CELL_SIZE: f64 = 25.0;
+
+type Cell = (i64, i64, i64);
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SpatialGrid {
+    cells: HashMap<Cell, HashSet<String>>,
+    entities: HashMap<String, Cell>, // The cell each entity is filed under
+}
+
+fn cell_of(position: &Position) -> Cell {
+    (
+        (position.x / CELL_SIZE).floor() as i64,
+        (position.y / CELL_SIZE).floor() as i64,
+        (position.z / CELL_SIZE).floor() as i64,
+    )
+}
+
+impl SpatialGrid {
+    /// File the entity under the cell containing its position, moving it if it changed cells
+    pub fn insert(&mut self, entity: &str, position: &Position) {
+        let cell = cell_of(position);
+        match self.entities.insert(entity.to_string(), cell) {
+            Some(old) if old == cell => {}
+            old => {
+                if let Some(old) = old {
+                    self.remove_from_cell(entity, old);
+                }
+                self.cells
+                    .entry(cell)
+                    .or_default()
+                    .insert(entity.to_string());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, entity: &str) {
+        if let Some(cell) = self.entities.remove(entity) {
+            self.remove_from_cell(entity, cell);
+        }
+    }
+
+    fn remove_from_cell(&mut self, entity: &str, cell: Cell) {
+        if let Some(entities) = self.cells.get_mut(&cell) {
+            entities.remove(entity);
+            if entities.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    /// The entities in every cell that overlaps the cube enclosing the sphere of `radius` around
+    /// `center`. Some of them may be further away than `radius`, so callers still need to check
+    pub fn query(&self, center: &Position, radius: f64) -> Vec<String> {
+        let min = cell_of(&Position::new(
+            center.x - radius,
+            center.y - radius,
+            center.z - radius,
+        ));
+        let max = cell_of(&Position::new(
+            center.x + radius,
+            center.y + radius,
+            center.z + radius,
+        ));
+        let within = |c: &Cell| {
+            (min.0..=max.0).contains(&c.0)
+                && (min.1..=max.1).contains(&c.1)
+                && (min.2..=max.2).contains(&c.2)
+        };
+        let span = |lo: i64, hi: i64| (hi - lo + 1) as usize;
+        let volume = span(min.0, max.0)
+            .saturating_mul(span(min.1, max.1))
+            .saturating_mul(span(min.2, max.2));
+        // A huge radius is cheaper to answer by going over the occupied cells instead
+        if volume > self.cells.len() {
+            return self
+                .cells
+                .iter()
+                .filter(|(c, _)| within(c))
+                .flat_map(|(_, entities)| entities.iter().cloned())
+                .collect();
+        }
+        let mut found = Vec::new();
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    if let Some(entities) = self.cells.get(&(x, y, z)) {
+                        found.extend(entities.iter().cloned());
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Position, SpatialGrid};
+
+    fn sorted(mut entities: Vec<String>) -> Vec<String> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn test_query() {
+        let mut grid = SpatialGrid::default();
+        grid.insert("near", &Position::new(10.0, 10.0, 0.0));
+        grid.insert("neighbor", &Position::new(-10.0, -20.0, 0.0));
+        grid.insert("beyond", &Position::new(0.0, 60.0, 0.0));
+        grid.insert("far", &Position::new(500.0, 0.0, 0.0));
+
+        let origin = Position::new(0.0, 0.0, 0.0);
+        assert_eq!(vec!["near", "neighbor"], sorted(grid.query(&origin, 20.0)));
+        assert_eq!(
+            vec!["beyond", "far", "near", "neighbor"],
+            sorted(grid.query(&origin, 100_000.0))
+        );
+
+        grid.insert("far", &Position::new(5.0, 0.0, 0.0));
+        grid.remove("neighbor");
+        assert_eq!(vec!["far", "near"], sorted(grid.query(&origin, 20.0)));
+        assert!(grid.query(&Position::new(500.0, 0.0, 0.0), 20.0).is_empty());
+    }
+}
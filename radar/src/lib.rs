@@ -37,6 +37,7 @@ const FRAMERATE: u32 = 1;
 const SYSTEM_NAME: &str = "radar";
 const RADAR_RECEIVER: &str = "radar_receiver";
 const POSITION: &str = "position";
+const VELOCITY: &str = "velocity";
 const REGISTRY_SUBJECT: &str = "decs.system.registry";
 
 pub fn handle_call(ctx: &CapabilitiesContext, operation: &str, msg: &[u8]) -> CallResult {
@@ -50,6 +51,8 @@ pub fn handle_call(ctx: &CapabilitiesContext, operation: &str, msg: &[u8]) -> Ca
 /// Routes message to corresponding function depending on the subject of the message
 /// `decs.system.registry` => handle_ping function for registry pings
 /// `event.decs.components.{shard}.{entity}.position.change` => handle_entity_position_change for caching positions
+/// `event.decs.components.{shard}.{entity}.position.delete` => handle_entity_position_delete for evicting them
+/// `event.decs.components.{shard}.{entity}.{signature|stealth|transponder|velocity|beacon|sensor_link|fleet}.*` => handle_profile_event for caching the rest of what radar picks up
 /// `decs.frames.{shard}.{system}` => handle_frame for updating an entities radar_contacts
 fn handle_message(
     ctx: &CapabilitiesContext,
@@ -67,8 +70,12 @@ fn handle_message(
 
         if subject == REGISTRY_SUBJECT {
             handle_ping(ctx, msg.unwrap())
-        } else if subject.starts_with("event.") && subject.ends_with(".change") {
+        } else if subject.starts_with("event.") && subject.ends_with(".position.change") {
             radar::handle_entity_position_change(ctx, msg.unwrap())
+        } else if subject.starts_with("event.") && subject.ends_with(".position.delete") {
+            radar::handle_entity_position_delete(ctx, msg.unwrap())
+        } else if subject.starts_with("event.") {
+            radar::handle_profile_event(ctx, msg.unwrap())
         } else if subject.starts_with("decs.frames.") && subject.ends_with(".radar") {
             radar::handle_frame(ctx, msg.unwrap())
         } else {
@@ -100,4 +107,5 @@ fn handle_ping(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> Call
     Ok(vec![])
 }
 
+mod grid;
 mod radar;
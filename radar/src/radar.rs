@@ -1,17 +1,181 @@
 extern crate waxosuit_guest as guest;
 
+use crate::grid::SpatialGrid;
 use decs::gateway::*;
 use guest::prelude::*;
 use stacktrader_types as trader;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
 use trader::components::*;
+use trader::contacts::{get_handles, put_handles, ContactHandles};
+
+/// Everything radar caches about the entities of one shard
+#[derive(Debug, Default)]
+struct ShardCache {
+    warm: bool, // Whether the shard's positions have been loaded from the KV store
+    positions: HashMap<String, Position>,
+    grid: SpatialGrid,                    // Spatial index of the positions
+    beacons: HashMap<String, Beacon>,     // Beacons are visible from afar, so they bypass the grid
+    links: HashMap<String, String>,       // The sensor network each linked entity asks to join
+    fleets: HashMap<String, Vec<String>>, // The members of the fleet each flagship commands
+    sizes: HashMap<String, f64>,          // Signature sizes of the entities that have a `signature`
+    stealth: HashMap<String, f64>, // Signature reductions of the entities that have `stealth`
+    transponders: HashMap<String, RadarTransponder>,
+    velocities: HashMap<String, Velocity>,
+    sightings: HashMap<String, (u64, HashMap<String, Relay>)>, // What each receiver saw directly, and in which frame
+}
+
+impl ShardCache {
+    fn insert(&mut self, entity: &str, position: Position) {
+        self.positions.insert(entity.to_string(), position);
+        self.grid.insert(entity, &position);
+    }
+
+    fn remove(&mut self, entity: &str) {
+        self.positions.remove(entity);
+        self.grid.remove(entity);
+        self.beacons.remove(entity);
+        self.links.remove(entity);
+        self.fleets.remove(entity);
+        self.sizes.remove(entity);
+        self.stealth.remove(entity);
+        self.transponders.remove(entity);
+        self.velocities.remove(entity);
+        self.sightings.remove(entity);
+    }
+
+    /// The entity's signature as radar sees it, after any stealth
+    fn signature(&self, entity: &str) -> f64 {
+        let size = self.sizes.get(entity).cloned().unwrap_or(DEFAULT_SIGNATURE);
+        let reduction = self.stealth.get(entity).cloned().unwrap_or(0.0);
+        size * (1.0 - reduction.clamp(0.0, 1.0))
+    }
+
+    /// The sensor network the entity belongs to: the one its link names, provided it's that
+    /// network's flagship or a member of the flagship's fleet
+    fn network(&self, entity: &str) -> Option<&String> {
+        self.links.get(entity).filter(|flagship| {
+            *flagship == entity
+                || self
+                    .fleets
+                    .get(flagship.as_str())
+                    .is_some_and(|members| members.iter().any(|m| m == entity))
+        })
+    }
+
+    /// The other entities in the same sensor network as the given one
+    fn linked(&self, entity: &str) -> Vec<String> {
+        match self.network(entity) {
+            Some(network) => self
+                .links
+                .keys()
+                .filter(|e| e.as_str() != entity && self.network(e) == Some(network))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// How the entity shows up on a radar receiver
+    fn profile(&self, entity: &str) -> Profile {
+        Profile {
+            signature: self.signature(entity),
+            transponder: self.transponders.get(entity).cloned(),
+            velocity: self.velocities.get(entity).cloned().unwrap_or_default(),
+            beacon: self.beacon(entity),
+            relay: None,
+        }
+    }
+
+    /// The entity's beacon. Starbases from before the `beacon` component existed don't have one,
+    /// so a starbase transponder counts as a beacon visible from anywhere
+    fn beacon(&self, entity: &str) -> Option<Beacon> {
+        self.beacons.get(entity).cloned().or_else(|| {
+            self.transponders
+                .get(entity)
+                .filter(|t| t.object_type == STARBASE_TYPE)
+                .map(|_| Beacon::default())
+        })
+    }
+
+    /// The entities radar treats as beacons
+    fn beacon_entities(&self) -> Vec<String> {
+        self.beacons
+            .keys()
+            .chain(
+                self.transponders
+                    .iter()
+                    .filter(|(_, t)| t.object_type == STARBASE_TYPE)
+                    .map(|(e, _)| e),
+            )
+            .cloned()
+            .collect()
+    }
+
+    /// The largest signature in the shard, which bounds how far away anything can be detected
+    fn max_signature(&self) -> f64 {
+        self.sizes
+            .values()
+            .cloned()
+            .fold(DEFAULT_SIGNATURE, f64::max)
+    }
+}
+
+/// What a radar receiver picks up from an entity: its signature, its transponder if it has one,
+/// how it's moving, its beacon if it's a landmark, and what linked receivers report about it
+#[derive(Debug, Clone, PartialEq)]
+struct Profile {
+    signature: f64,
+    transponder: Option<RadarTransponder>,
+    velocity: Velocity,
+    beacon: Option<Beacon>,
+    relay: Option<Relay>,
+}
+
+/// How well the receivers in a sensor network see an entity directly, taking the best of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Relay {
+    confidence: f64,
+    identified: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            signature: DEFAULT_SIGNATURE,
+            transponder: Some(RadarTransponder::default()),
+            velocity: Velocity::default(),
+            beacon: None,
+            relay: None,
+        }
+    }
+}
 
 lazy_static! {
-    static ref POSITIONS: RwLock<HashMap<String, Position>> = RwLock::new(HashMap::new());
+    static ref SHARDS: RwLock<HashMap<String, ShardCache>> = RwLock::new(HashMap::new());
 }
 
 const RADAR_CONTACTS: &str = "radar_contacts";
+const SIGNATURE: &str = "signature";
+const STEALTH: &str = "stealth";
+const TRANSPONDER: &str = "transponder";
+const VELOCITY: &str = super::VELOCITY;
+// The object type receivers filter unidentified contacts by
+const UNKNOWN_OBJECT_TYPE: &str = "unknown";
+
+const DEFAULT_SIGNATURE: f64 = 1.0;
+/// The signal-to-noise ratio above which a contact is detected. Signals fall off with the fourth
+/// power of distance (the radar equation), so a receiver with a sensitivity of 1.0 detects a
+/// signature of 1.0 right up to its radius
+const DETECTION_THRESHOLD: f64 = 1.0;
+/// The signal-to-noise ratio at which a contact is a clear blip (a confidence of 1.0), e.g. a
+/// signature of 1.0 at half the receiver's radius
+const CLEAR_SNR: f64 = 16.0;
+const BEACON: &str = "beacon";
+// The object type of starbases, which are beacons whether or not they have a `beacon` component
+const STARBASE_TYPE: &str = "starbase";
+const SENSOR_LINK: &str = "sensor_link";
+const FLEET: &str = "fleet";
 
 pub(crate) fn handle_frame(ctx: &CapabilitiesContext, msg: messaging::BrokerMessage) -> CallResult {
     let frame: decs::systemmgr::EntityFrame = serde_json::from_slice(&msg.body)?;
@@ -30,132 +194,428 @@ pub(crate) fn handle_frame(ctx: &CapabilitiesContext, msg: messaging::BrokerMess
         super::POSITION
     ))?;
 
+    let velocity_value = ctx.kv().get(&format!(
+        "decs:components:{}:{}:{}",
+        frame.shard,
+        frame.entity_id,
+        super::VELOCITY
+    ))?;
+
     let resource_id = format!("decs.components.{}.{}", frame.shard, frame.entity_id);
 
     if let (Some(radar_str), Some(position_str)) = (radar_receiver_value, position_value) {
         let radar_receiver: RadarReceiver = serde_json::from_str(&radar_str)?;
         let position: Position = serde_json::from_str(&position_str)?;
+        let velocity: Velocity = match velocity_value {
+            Some(velocity_str) => serde_json::from_str(&velocity_str)?,
+            None => Velocity::default(),
+        };
 
-        let radar_contacts_key = &format!(
-            "decs:components:{}:{}:{}",
-            frame.shard, frame.entity_id, RADAR_CONTACTS
-        );
+        let listed = load_contacts(ctx, &frame.shard, &frame.entity_id)?;
+        let mut handles = get_handles(ctx, &frame.shard, &frame.entity_id)?;
+        let old_contacts = resolve_contacts(&listed, &handles);
 
-        let old_contacts: HashMap<String, RadarContact> = ctx
-            .kv()
-            .list_range(radar_contacts_key, 0, -1)? // Get all items of a list from index 0 to the last item
-            .iter()
-            .filter_map(|c| {
-                if let Ok(Some(contact_str)) = ctx.kv().get(&c.replace(".", ":")) {
-                    if let Some(contact) = serde_json::from_str(&contact_str).unwrap_or(None) {
-                        Some((c, contact))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .fold(
-                HashMap::<String, RadarContact>::new(),
-                |mut acc, (c, contact)| {
-                    acc.insert(c.to_string(), contact);
-                    acc
-                },
-            );
+        warm_up(ctx, &frame.shard)?;
+
+        let relays = linked_sightings(ctx, &frame.shard, &frame.entity_id, frame.seq_no)?;
 
         let updates = {
-            let all_positions = POSITIONS.read().unwrap().clone();
-
-            // If the positions cache is ever empty, ensure that all previously existing entities
-            // are loaded into that cache
-            if all_positions.is_empty() {
-                let entities = ctx.kv().set_intersect(&vec![
-                    format!("decs:{}:transponder:entities", frame.shard),
-                    format!("decs:{}:position:entities", frame.shard),
-                ])?;
-                for entity in entities {
-                    if let Ok(Some(position_str)) = ctx.kv().get(&format!(
-                        "decs:components:{}:{}:position",
-                        frame.shard, entity
-                    )) {
-                        ctx.log(&format!(
-                            "Adding entity {} at position {} to the cache",
-                            entity, position_str
-                        ));
-                        POSITIONS
-                            .write()
-                            .unwrap()
-                            .insert(entity, serde_json::from_str(&position_str)?);
-                    }
-                }
-                ctx.log(&format!(
-                    "Cache repleted with {} entities",
-                    POSITIONS.read().unwrap().len()
-                ));
-            }
+            let (candidates, profiles) = nearby_positions(
+                &frame.shard,
+                &position,
+                &radar_receiver,
+                &old_contacts,
+                &relays,
+            );
 
             radar_updates(
                 &frame.entity_id,
                 &frame.shard,
                 &position,
+                &velocity,
                 &radar_receiver,
                 &old_contacts,
-                &all_positions,
-                Some(&ctx),
+                &candidates,
+                &profiles,
+                frame.seq_no,
             )
         };
 
-        let _results = updates
-            .iter()
-            .map(|update| match update {
-                RadarContactDelta::Add(rc) => (
-                    ResProtocolRequest::New(format!(
-                        "{}.{}",
-                        resource_id.to_string(),
-                        RADAR_CONTACTS
-                    ))
-                    .to_string()
-                    .clone(),
-                    serde_json::json!({ "params": rc }),
-                ),
-                RadarContactDelta::Remove(rid) => (
-                    ResProtocolRequest::Delete(format!(
-                        "{}.{}",
-                        resource_id.to_string(),
-                        RADAR_CONTACTS
-                    ))
-                    .to_string(),
-                    serde_json::json!({"params": {"rid": rid.replace(":", ".")}}),
-                ),
-                RadarContactDelta::Change(rid, rc) => (
-                    format!("call.{}.set", rid.clone()),
-                    serde_json::json!({ "params": rc }),
-                ),
-            })
-            .map(|(subject, payload)| publish_message(ctx, &subject, payload))
-            .collect::<Vec<CallResult>>();
-
-        // If we modified a player's contacts at all, publish a change message to make
-        // RESgate requery the source of truth.
-        if !updates.is_empty() {
-            ctx.msg().publish(
-                "system.reset",
-                None,
-                &serde_json::to_vec(&serde_json::json!({
-                    "resources":
-                        [format!(
-                            "decs.components.{}.{}.radar_contacts",
-                            frame.shard, frame.entity_id
-                        )]
-                }))?,
-            )?;
+        // Contacts are added and removed through the component manager, which tells RESgate
+        // exactly what changed rather than having it requery the whole collection
+        let collection = format!("{}.{}", resource_id, RADAR_CONTACTS);
+        let seen = sightings_after(&old_contacts, &updates);
+        let on_list: HashSet<String> = contacts_after(&old_contacts, &updates)
+            .into_iter()
+            .map(|rc| rc.entity_id.clone())
+            .collect();
+        let old_handles = handles.clone();
+        for update in updates {
+            let update = disguise(update, &mut handles);
+            apply_update(ctx, &collection, &listed, update)?;
+        }
+        // A handle only stands for its entity while the entity is on the list
+        handles
+            .entities
+            .retain(|_, entity| on_list.contains(entity));
+        if handles != old_handles {
+            put_handles(ctx, &frame.shard, &frame.entity_id, &handles)?;
         }
+        // Remembered so linked receivers don't have to read this receiver's contacts back
+        SHARDS
+            .write()
+            .unwrap()
+            .entry(frame.shard.clone())
+            .or_default()
+            .sightings
+            .insert(frame.entity_id.clone(), (frame.seq_no, seen));
     }
 
     Ok(vec![])
 }
 
+/// Reads the contacts in an entity's `radar_contacts` collection by rid
+fn load_contacts(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<HashMap<String, RadarContact>, Box<dyn std::error::Error>> {
+    let radar_contacts_key = format!("decs:components:{}:{}:{}", shard, entity, RADAR_CONTACTS);
+    Ok(ctx
+        .kv()
+        .list_range(&radar_contacts_key, 0, -1)? // Get all items of a list from index 0 to the last item
+        .iter()
+        .filter_map(|rid| {
+            let contact_str = ctx.kv().get(&rid.replace(".", ":")).ok()??;
+            let contact = serde_json::from_str(&contact_str).ok()?;
+            Some((rid.to_string(), contact))
+        })
+        .collect())
+}
+
+/// Gathers the contacts the other receivers in the entity's sensor network see directly. Contacts
+/// they only have relayed themselves aren't passed on, so nothing echoes around the network.
+/// Sightings cached during this frame or the one before are used as they are; those of receivers
+/// this instance hasn't computed lately (e.g. right after a restart) are read from the KV store
+fn linked_sightings(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    seq_no: u64,
+) -> std::result::Result<HashMap<String, Relay>, Box<dyn std::error::Error>> {
+    let members: Vec<(String, Option<HashMap<String, Relay>>)> =
+        match SHARDS.read().unwrap().get(shard) {
+            Some(cache) => cache
+                .linked(entity)
+                .into_iter()
+                .map(|member| {
+                    let cached = cache
+                        .sightings
+                        .get(&member)
+                        .filter(|(at, _)| *at == seq_no || *at + 1 == seq_no)
+                        .map(|(_, seen)| seen.clone());
+                    (member, cached)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+    let mut relays: HashMap<String, Relay> = HashMap::new();
+    for (member, cached) in members {
+        let seen = match cached {
+            Some(seen) => seen,
+            None => {
+                let listed = load_contacts(ctx, shard, &member)?;
+                let handles = get_handles(ctx, shard, &member)?;
+                sightings(resolve_contacts(&listed, &handles).values())
+            }
+        };
+        for (target, sighting) in seen {
+            if target == entity {
+                continue;
+            }
+            let relay = relays.entry(target).or_insert(Relay {
+                confidence: 0.0,
+                identified: false,
+            });
+            relay.confidence = relay.confidence.max(sighting.confidence);
+            relay.identified |= sighting.identified;
+        }
+    }
+    Ok(relays)
+}
+
+/// How well a receiver sees each of the entities among its contacts directly
+fn sightings<'a>(contacts: impl Iterator<Item = &'a RadarContact>) -> HashMap<String, Relay> {
+    let mut seen: HashMap<String, Relay> = HashMap::new();
+    for contact in contacts.filter(|c| !c.relayed) {
+        let sighting = seen.entry(contact.entity_id.clone()).or_insert(Relay {
+            confidence: 0.0,
+            identified: false,
+        });
+        sighting.confidence = sighting.confidence.max(contact.confidence);
+        sighting.identified |= contact.transponder.is_some();
+    }
+    seen
+}
+
+/// What a receiver sees directly once the given updates are applied to its contacts
+fn sightings_after(
+    old_contacts: &HashMap<String, RadarContact>,
+    updates: &[RadarContactDelta],
+) -> HashMap<String, Relay> {
+    sightings(contacts_after(old_contacts, updates).into_iter())
+}
+
+/// A receiver's contacts once the given updates are applied to them
+fn contacts_after<'a>(
+    old_contacts: &'a HashMap<String, RadarContact>,
+    updates: &'a [RadarContactDelta],
+) -> Vec<&'a RadarContact> {
+    let mut contacts: HashMap<String, &RadarContact> = old_contacts
+        .iter()
+        .map(|(rid, rc)| (rid.replace(":", "."), rc))
+        .collect();
+    let mut added = Vec::new();
+    for update in updates {
+        match update {
+            RadarContactDelta::Add(rc) => added.push(rc),
+            RadarContactDelta::Remove(rid) => {
+                contacts.remove(rid);
+            }
+            RadarContactDelta::Change(rid, rc) => {
+                contacts.insert(rid.clone(), rc);
+            }
+        }
+    }
+    contacts.into_values().chain(added).collect()
+}
+
+/// The contacts as listed, with the entity behind each unidentified contact's handle in place of
+/// the handle
+fn resolve_contacts(
+    listed: &HashMap<String, RadarContact>,
+    handles: &ContactHandles,
+) -> HashMap<String, RadarContact> {
+    listed
+        .iter()
+        .map(|(rid, rc)| {
+            let entity = match handles.entity(&rc.entity_id) {
+                Some(entity) if rc.transponder.is_none() => entity.clone(),
+                _ => rc.entity_id.clone(),
+            };
+            let contact = RadarContact {
+                entity_id: entity,
+                ..rc.clone()
+            };
+            (rid.clone(), contact)
+        })
+        .collect()
+}
+
+/// The update as it's listed: an unidentified contact shows the handle the receiver lists its
+/// entity under rather than the entity's ID
+fn disguise(update: RadarContactDelta, handles: &mut ContactHandles) -> RadarContactDelta {
+    let mut hide = |rc: RadarContact| match rc.transponder {
+        Some(_) => rc,
+        None => RadarContact {
+            entity_id: handles.handle_for(&rc.entity_id),
+            ..rc
+        },
+    };
+    match update {
+        RadarContactDelta::Add(rc) => RadarContactDelta::Add(hide(rc)),
+        RadarContactDelta::Change(rid, rc) => RadarContactDelta::Change(rid, hide(rc)),
+        RadarContactDelta::Remove(rid) => RadarContactDelta::Remove(rid),
+    }
+}
+
+/// Gathers the cached positions and profiles of the entities a radar receiver needs to consider:
+/// those in the grid cells within range of the largest signature in the shard, the beacons, those
+/// relayed by its sensor network, and its current contacts (so they can be removed once out of
+/// range)
+fn nearby_positions(
+    shard: &str,
+    position: &Position,
+    radar_receiver: &RadarReceiver,
+    old_contacts: &HashMap<String, RadarContact>,
+    relays: &HashMap<String, Relay>,
+) -> (HashMap<String, Position>, HashMap<String, Profile>) {
+    let shards = SHARDS.read().unwrap();
+    let cache = match shards.get(shard) {
+        Some(cache) => cache,
+        None => return (HashMap::new(), HashMap::new()),
+    };
+    let range = detection_range(radar_receiver, cache.max_signature());
+    let mut entities = cache.grid.query(position, range);
+    entities.extend(cache.beacon_entities());
+    entities.extend(relays.keys().cloned());
+    entities.extend(old_contacts.values().map(|rc| rc.entity_id.clone()));
+    let positions: HashMap<_, _> = entities
+        .into_iter()
+        .filter_map(|e| cache.positions.get(&e).map(|p| (e, *p)))
+        .collect();
+    let profiles = positions
+        .keys()
+        .map(|e| {
+            let profile = Profile {
+                relay: relays.get(e).cloned(),
+                ..cache.profile(e)
+            };
+            (e.clone(), profile)
+        })
+        .collect();
+    (positions, profiles)
+}
+
+/// The first time a shard is seen, load the positions of all its existing entities into its
+/// cache. Positions that changed in the meantime are already cached and left as they are
+fn warm_up(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if SHARDS
+        .read()
+        .unwrap()
+        .get(shard)
+        .filter(|c| c.warm)
+        .is_some()
+    {
+        return Ok(());
+    }
+    let positions: Vec<(String, Position)> = load_components(ctx, shard, super::POSITION)?;
+    let sizes: Vec<(String, RadarSignature)> = load_components(ctx, shard, SIGNATURE)?;
+    let stealth: Vec<(String, Stealth)> = load_components(ctx, shard, STEALTH)?;
+    let transponders: Vec<(String, RadarTransponder)> = load_components(ctx, shard, TRANSPONDER)?;
+    let velocities: Vec<(String, Velocity)> = load_components(ctx, shard, VELOCITY)?;
+    let beacons: Vec<(String, Beacon)> = load_components(ctx, shard, BEACON)?;
+    let links: Vec<(String, SensorLink)> = load_components(ctx, shard, SENSOR_LINK)?;
+    let fleets: Vec<(String, Fleet)> = load_components(ctx, shard, FLEET)?;
+    let mut shards = SHARDS.write().unwrap();
+    let cache = shards.entry(shard.to_string()).or_default();
+    for (entity, position) in positions {
+        if !cache.positions.contains_key(&entity) {
+            cache.insert(&entity, position);
+        }
+    }
+    for (entity, signature) in sizes {
+        cache.sizes.entry(entity).or_insert(signature.size);
+    }
+    for (entity, stealth) in stealth {
+        cache.stealth.entry(entity).or_insert(stealth.reduction);
+    }
+    for (entity, transponder) in transponders {
+        cache.transponders.entry(entity).or_insert(transponder);
+    }
+    for (entity, velocity) in velocities {
+        cache.velocities.entry(entity).or_insert(velocity);
+    }
+    for (entity, beacon) in beacons {
+        cache.beacons.entry(entity).or_insert(beacon);
+    }
+    for (entity, link) in links {
+        cache.links.entry(entity).or_insert(link.network);
+    }
+    for (entity, fleet) in fleets {
+        cache.fleets.entry(entity).or_insert(fleet.members);
+    }
+    cache.warm = true;
+    ctx.log(&format!(
+        "Cache for shard {} warmed up with {} entities",
+        shard,
+        cache.positions.len()
+    ));
+    Ok(())
+}
+
+/// Reads the given component of every entity in the shard that has one
+fn load_components<T: serde::de::DeserializeOwned>(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    component: &str,
+) -> std::result::Result<Vec<(String, T)>, Box<dyn std::error::Error>> {
+    let mut components = Vec::new();
+    for entity in ctx
+        .kv()
+        .set_members(&format!("decs:{}:{}:entities", shard, component))?
+    {
+        if let Some(value) = ctx.kv().get(&format!(
+            "decs:components:{}:{}:{}",
+            shard, entity, component
+        ))? {
+            components.push((entity, serde_json::from_str(&value)?));
+        }
+    }
+    Ok(components)
+}
+
+/// Applies a single change to a `radar_contacts` collection. New and lost contacts go through the
+/// component manager, which keeps the collection's list and publishes the RES `add` and `remove`
+/// events; a changed contact is written in place along with a `change` event carrying only the
+/// fields that differ
+fn apply_update(
+    ctx: &CapabilitiesContext,
+    collection: &str,
+    old_contacts: &HashMap<String, RadarContact>,
+    update: RadarContactDelta,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    match update {
+        RadarContactDelta::Add(rc) => {
+            let newreq = ResProtocolRequest::New(collection.to_string());
+            publish_message(
+                ctx,
+                &newreq.to_string(),
+                serde_json::json!({ "params": rc }),
+            )?;
+        }
+        RadarContactDelta::Remove(rid) => {
+            let delreq = ResProtocolRequest::Delete(collection.to_string());
+            publish_message(
+                ctx,
+                &delreq.to_string(),
+                serde_json::json!({ "params": { "rid": rid } }),
+            )?;
+        }
+        RadarContactDelta::Change(rid, rc) => {
+            let values = match old_contacts.get(&rid) {
+                Some(old) => changed_values(old, &rc)?,
+                None => serde_json::to_value(&rc)?
+                    .as_object()
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            if !values.is_empty() {
+                ctx.kv()
+                    .set(&rid.replace(".", ":"), &serde_json::to_string(&rc)?, None)?;
+                publish_message(
+                    ctx,
+                    &format!("event.{}.change", rid),
+                    serde_json::json!({ "values": values }),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The fields of a contact that differ from its previous state, as sent in a RES change event
+fn changed_values(
+    old: &RadarContact,
+    new: &RadarContact,
+) -> std::result::Result<serde_json::Map<String, serde_json::Value>, Box<dyn std::error::Error>> {
+    let old = serde_json::to_value(old)?;
+    let new = serde_json::to_value(new)?;
+    Ok(new
+        .as_object()
+        .map(|fields| {
+            fields
+                .iter()
+                .filter(|(field, value)| old.get(field.as_str()) != Some(value))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
 /// Helper function used to publish a payload on a specified subjct
 fn publish_message(
     ctx: &CapabilitiesContext,
@@ -174,81 +634,148 @@ fn publish_message(
 }
 
 /// Function to compute all changes to a contact list needed given a resources id, current position,
-/// radar receiver, all old contacts, and a map of all entity positions that are published.
+/// radar receiver, all old contacts, a map of all entity positions that are published and their
+/// profiles (a signature of 1.0 and an active transponder for any that are missing). Contacts are
+/// only linked to their transponder while it's switched on, or once they're within the receiver's
+/// `identify_range`; the others are unidentified. Each contact carries its velocity and how fast
+/// it's closing in on `current_velocity`. Only entities the receiver's beam swept over during
+/// frame `seq_no` are refreshed; the others keep whatever contact they had, as long as the beam
+/// will sweep over them again. Beacons are detected
+/// however far away they are, up to their own range if they have one. Entities the receiver
+/// doesn't see itself but its sensor network does are relayed contacts.
+/// The receiver never lists itself, even if it's a beacon. Contacts the receiver filters out are
+/// dropped, and of the rest only the nearest `max_contacts` are kept besides any beacons.
 /// Changes are in the form of RadarContactDeltas, either specifying to Add, Remove, or Change a contact.
+/// Old contacts whose entity no longer has a position (e.g. because it was deleted) are removed.
+#[allow(clippy::too_many_arguments)]
 fn radar_updates(
     entity_id: &str,
     shard: &str,
     current_position: &Position,
+    current_velocity: &Velocity,
     radar_receiver: &RadarReceiver,
     old_contacts: &HashMap<String, RadarContact>,
     all_positions: &HashMap<String, Position>,
-    ctx: Option<&CapabilitiesContext>,
+    profiles: &HashMap<String, Profile>,
+    seq_no: u64,
 ) -> Vec<RadarContactDelta> {
-    let contacts: Vec<String> = old_contacts
+    let contacts: HashMap<&str, String> = old_contacts
+        .iter()
+        .map(|(rid, rc)| (rc.entity_id.as_str(), rid.replace(":", ".")))
+        .collect();
+    let seen_directly: HashSet<&str> = old_contacts
         .values()
-        .map(|rc| rc.entity_id.clone())
+        .filter(|rc| !rc.relayed)
+        .map(|rc| rc.entity_id.as_str())
         .collect();
-    all_positions
+    // Every entity that makes the list, with its refreshed contact or None to keep the old one, and
+    // whether it's a beacon
+    let mut visible: Vec<(f64, &str, Option<RadarContact>, bool)> = all_positions
         .iter()
         .filter_map(|(ent_id, pos)| {
-            if contacts.contains(ent_id) {
-                let mut rid: String = "".to_string();
-                if let Some((entity_rid, _val)) =
-                    old_contacts.iter().find(|(_k, v)| v.entity_id == *ent_id)
-                {
-                    rid = entity_rid.to_string().replace(":", ".");
+            if entity_id == ent_id {
+                return None;
+            }
+            let profile = profiles.get(ent_id).cloned().unwrap_or_default();
+            let distance = current_position.distance_to_3d(pos);
+            let beacon = profile
+                .beacon
+                .as_ref()
+                .is_some_and(|b| b.range.is_none_or(|range| distance <= range));
+            let identified = match &profile.transponder {
+                Some(transponder) => {
+                    transponder.active
+                        || distance <= radar_receiver.identify_range
+                        || profile.relay.is_some_and(|r| r.identified)
                 }
-                if ctx.is_some()
-                    && !ctx
-                        .unwrap()
-                        .kv()
-                        .exists(&format!("decs:components:{}:{}:transponder", shard, ent_id))
-                        .unwrap_or(true)
-                {
-                    ctx.unwrap().log(&format!("Removing: {}", ent_id));
-                    POSITIONS.write().unwrap().remove(ent_id);
-                    Some(RadarContactDelta::Remove(rid))
-                } else if within_radius(current_position, pos, radar_receiver.radius)
-                    || ent_id == "starbase_0"
+                None => false,
+            };
+            if !passes_filters(radar_receiver, &profile, identified) {
+                return None;
+            }
+            let vector_to = current_position.vector_to(pos);
+            let swept = in_beam(radar_receiver, seq_no, &vector_to);
+            // Beacons in range are always visible, and always clearly
+            let direct = if !swept {
+                None
+            } else if beacon {
+                Some(1.0)
+            } else {
+                detect(current_position, pos, radar_receiver, profile.signature)
+            };
+            let (confidence, relayed) = match (direct, profile.relay) {
+                (Some(confidence), _) => (confidence, false),
+                // Out of the beam, contacts seen directly keep whatever they had until the beam
+                // comes round again. One it never comes round to is dropped rather than kept forever
+                (None, _)
+                    if !swept
+                        && in_coverage(radar_receiver, &vector_to)
+                        && seen_directly.contains(ent_id.as_str()) =>
                 {
-                    let vector_to = current_position.vector_to(pos);
-                    let transponder = transponder_for_entity(shard, &ent_id.clone());
-                    Some(RadarContactDelta::Change(
-                        rid,
-                        RadarContact {
-                            entity_id: ent_id.clone().to_string(),
-                            distance: vector_to.mag,
-                            distance_xy: vector_to.distance_xy,
-                            azimuth: vector_to.azimuth,
-                            elevation: vector_to.elevation,
-                            transponder,
-                        },
-                    ))
-                } else {
-                    Some(RadarContactDelta::Remove(rid))
+                    return Some((distance, ent_id.as_str(), None, beacon));
                 }
-            } else if (entity_id != ent_id
-                && within_radius(current_position, &pos, radar_receiver.radius))
-                || ent_id == "starbase_0"
-            {
-                let vector_to = current_position.vector_to(pos);
-                let transponder = transponder_for_entity(shard, &ent_id.clone());
-                Some(RadarContactDelta::Add(RadarContact {
-                    entity_id: ent_id.clone().to_string(),
-                    distance: vector_to.mag,
-                    distance_xy: vector_to.distance_xy,
-                    azimuth: vector_to.azimuth,
-                    elevation: vector_to.elevation,
-                    transponder,
-                }))
-            } else {
-                None
-            }
+                (None, Some(relay)) => (relay.confidence, true),
+                (None, None) => return None,
+            };
+            let (closing_speed, closest_approach_secs) =
+                closing(current_position, current_velocity, pos, &profile.velocity);
+            let contact = RadarContact {
+                entity_id: ent_id.to_string(),
+                distance: vector_to.mag,
+                distance_xy: vector_to.distance_xy,
+                azimuth: vector_to.azimuth,
+                elevation: vector_to.elevation,
+                transponder: if identified {
+                    Some(transponder_for_entity(shard, ent_id))
+                } else {
+                    None
+                },
+                confidence,
+                velocity: profile.velocity,
+                closing_speed,
+                closest_approach_secs,
+                relayed,
+            };
+            Some((distance, ent_id.as_str(), Some(contact), beacon))
         })
+        .collect();
+    if let Some(max_contacts) = radar_receiver.max_contacts {
+        // Beacons are landmarks rather than contacts, so they never push other contacts off the list
+        let (beacons, mut others): (Vec<_>, Vec<_>) = visible.into_iter().partition(|v| v.3);
+        others.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(a.1.cmp(b.1)));
+        others.truncate(max_contacts as usize);
+        visible = beacons.into_iter().chain(others).collect();
+    }
+    let kept: HashSet<&str> = visible.iter().map(|(_, ent_id, _, _)| *ent_id).collect();
+    visible
+        .into_iter()
+        .filter_map(|(_, ent_id, contact, _)| {
+            let contact = contact?;
+            Some(match contacts.get(ent_id) {
+                Some(rid) => RadarContactDelta::Change(rid.clone(), contact),
+                None => RadarContactDelta::Add(contact),
+            })
+        })
+        .chain(
+            contacts
+                .iter()
+                .filter(|(ent_id, _rid)| !kept.contains(*ent_id))
+                .map(|(_ent_id, rid)| RadarContactDelta::Remove(rid.clone())),
+        )
         .collect::<Vec<RadarContactDelta>>()
 }
 
+/// Whether a contact makes it through the receiver's filters. Unidentified contacts can only be
+/// told apart as being of the unknown object type
+fn passes_filters(radar_receiver: &RadarReceiver, profile: &Profile, identified: bool) -> bool {
+    let transponder = profile.transponder.as_ref().filter(|_| identified);
+    let object_type = transponder.map_or(UNKNOWN_OBJECT_TYPE, |t| t.object_type.as_str());
+    let depleted = transponder.is_some_and(|t| t.is_depleted());
+    (radar_receiver.object_types.is_empty()
+        || radar_receiver.object_types.iter().any(|t| t == object_type))
+        && !(radar_receiver.hide_depleted && depleted)
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 enum RadarContactDelta {
     Add(RadarContact),
@@ -257,7 +784,7 @@ enum RadarContactDelta {
 }
 
 /// Receives messages on the subject `event.decs.components.{shard}.{entity}.position.change`
-/// Stores entity position in-memory in the POSITIONS HashMap
+/// Stores entity position in-memory in the shard's cache and spatial grid
 /// The cache is used later to discover nearby radar_contacts
 pub(crate) fn handle_entity_position_change(
     _ctx: &CapabilitiesContext,
@@ -266,10 +793,131 @@ pub(crate) fn handle_entity_position_change(
     let subject: Vec<&str> = msg.subject.split('.').collect();
     let position_value: serde_json::Value = serde_json::from_slice(&msg.body)?;
     let position: Position = serde_json::from_value::<Position>(position_value["values"].clone())?;
-    POSITIONS
+    SHARDS
         .write()
         .unwrap()
-        .insert(subject[4].to_string(), position);
+        .entry(subject[3].to_string())
+        .or_default()
+        .insert(subject[4], position);
+    Ok(vec![])
+}
+
+/// Receives messages on the subjects `event.decs.components.{shard}.{entity}.signature.{change|delete}`,
+/// `event.decs.components.{shard}.{entity}.stealth.{change|delete}`,
+/// `event.decs.components.{shard}.{entity}.transponder.{change|delete}`,
+/// `event.decs.components.{shard}.{entity}.velocity.{change|delete}`,
+/// `event.decs.components.{shard}.{entity}.beacon.{change|delete}`,
+/// `event.decs.components.{shard}.{entity}.sensor_link.{change|delete}` and
+/// `event.decs.components.{shard}.{entity}.fleet.{change|delete}`
+/// Keeps the shard's cached signatures, transponders, velocities, beacons, sensor links and fleets
+/// up to date
+pub(crate) fn handle_profile_event(
+    _ctx: &CapabilitiesContext,
+    msg: messaging::BrokerMessage,
+) -> CallResult {
+    let subject: Vec<&str> = msg.subject.split('.').collect();
+    if subject.len() != 7 {
+        return Err(format!("Unexpected profile subject: {}", msg.subject).into());
+    }
+    let (shard, entity) = (subject[3], subject[4].to_string());
+    let mut shards = SHARDS.write().unwrap();
+    let cache = shards.entry(shard.to_string()).or_default();
+    match (subject[5], subject[6]) {
+        (SIGNATURE, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let signature: RadarSignature = serde_json::from_value(value["values"].clone())?;
+            cache.sizes.insert(entity, signature.size);
+        }
+        (STEALTH, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let stealth: Stealth = serde_json::from_value(value["values"].clone())?;
+            cache.stealth.insert(entity, stealth.reduction);
+        }
+        (TRANSPONDER, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let transponder = cache.transponders.entry(entity).or_default();
+            *transponder = merge_change(transponder, &value["values"])?;
+        }
+        (SIGNATURE, "delete") => {
+            cache.sizes.remove(&entity);
+        }
+        (STEALTH, "delete") => {
+            cache.stealth.remove(&entity);
+        }
+        (VELOCITY, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let velocity = cache.velocities.entry(entity).or_default();
+            *velocity = merge_change(velocity, &value["values"])?;
+        }
+        (TRANSPONDER, "delete") => {
+            cache.transponders.remove(&entity);
+        }
+        (VELOCITY, "delete") => {
+            cache.velocities.remove(&entity);
+        }
+        (BEACON, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let beacon = cache.beacons.entry(entity).or_default();
+            *beacon = merge_change(beacon, &value["values"])?;
+        }
+        (BEACON, "delete") => {
+            cache.beacons.remove(&entity);
+        }
+        (SENSOR_LINK, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let link = SensorLink {
+                network: cache.links.get(&entity).cloned().unwrap_or_default(),
+            };
+            cache
+                .links
+                .insert(entity, merge_change(&link, &value["values"])?.network);
+        }
+        (SENSOR_LINK, "delete") => {
+            cache.links.remove(&entity);
+        }
+        (FLEET, "change") => {
+            let value: serde_json::Value = serde_json::from_slice(&msg.body)?;
+            let fleet = Fleet {
+                members: cache.fleets.get(&entity).cloned().unwrap_or_default(),
+            };
+            cache
+                .fleets
+                .insert(entity, merge_change(&fleet, &value["values"])?.members);
+        }
+        (FLEET, "delete") => {
+            cache.fleets.remove(&entity);
+        }
+        _ => return Err(format!("Unexpected profile subject: {}", msg.subject).into()),
+    }
+    Ok(vec![])
+}
+
+/// A change event only carries the fields that changed, so they're laid over the cached component
+fn merge_change<T: serde::Serialize + serde::de::DeserializeOwned>(
+    cached: &T,
+    values: &serde_json::Value,
+) -> std::result::Result<T, Box<dyn std::error::Error>> {
+    let mut merged = serde_json::to_value(cached)?;
+    if let (Some(fields), Some(changed)) = (merged.as_object_mut(), values.as_object()) {
+        fields.extend(changed.clone());
+    }
+    Ok(serde_json::from_value(merged)?)
+}
+
+/// Receives messages on the subject `event.decs.components.{shard}.{entity}.position.delete`
+/// An entity without a position can't be detected, so it's evicted from the shard's cache and
+/// disappears from every radar on the next frame
+pub(crate) fn handle_entity_position_delete(
+    _ctx: &CapabilitiesContext,
+    msg: messaging::BrokerMessage,
+) -> CallResult {
+    let subject: Vec<&str> = msg.subject.split('.').collect();
+    if subject.len() != 7 {
+        return Err(format!("Unexpected deletion subject: {}", msg.subject).into());
+    }
+    if let Some(cache) = SHARDS.write().unwrap().get_mut(subject[3]) {
+        cache.remove(subject[4]);
+    }
     Ok(vec![])
 }
 
@@ -278,6 +926,117 @@ fn within_radius(entity: &Position, target: &Position, radius: f64) -> bool {
     entity.distance_to_3d(target) <= radius
 }
 
+/// How much a beam focused into a cone amplifies the signal, compared to radiating all around: the
+/// ratio of the full sphere's solid angle to the cone's
+fn beam_gain(radar_receiver: &RadarReceiver) -> f64 {
+    let half_angle = (radar_receiver.fov.clamp(1.0, 360.0) / 2.0).to_radians();
+    2.0 / (1.0 - half_angle.cos())
+}
+
+/// Determines whether the target lies in the part of the sky the receiver's beam covered during
+/// frame `seq_no`. The beam lies in the horizontal plane and turns `sweep_rate` degrees a second,
+/// so it covers every direction within `fov / 2` of the arc it turned through during the frame.
+/// Receivers don't store where their beam points, so its angle is worked out from the frame
+/// number alone: every receiver with the same settings sweeps in step, and the beam jumps back to
+/// `azimuth` whenever the game loop restarts and numbers its frames from scratch
+fn in_beam(radar_receiver: &RadarReceiver, seq_no: u64, vector: &TargetVector) -> bool {
+    if radar_receiver.fov >= 360.0 || vector.mag == 0 {
+        return true;
+    }
+    let turn = radar_receiver.sweep_rate / f64::from(super::FRAMERATE);
+    let end = radar_receiver.azimuth + turn * seq_no as f64;
+    let (start, end) = if turn < 0.0 {
+        (end, end - turn)
+    } else {
+        (end - turn, end)
+    };
+    // Azimuthal distance from the target to the nearest point of the arc
+    let middle = (start + end) / 2.0;
+    let offset = (vector.azimuth - middle + 180.0).rem_euclid(360.0) - 180.0;
+    let off_arc = (offset.abs() - (end - start) / 2.0).max(0.0).to_radians();
+    // Elevation is measured from straight up, so the horizontal plane lies at 90 degrees
+    let off_plane = (vector.elevation - 90.0).to_radians();
+    let off_beam = (off_plane.cos() * off_arc.cos()).acos().to_degrees();
+    off_beam <= radar_receiver.fov / 2.0
+}
+
+/// Determines whether the receiver's beam ever sweeps over the target. A beam that doesn't turn
+/// only covers its own cone, while a turning one covers everything within `fov / 2` of the
+/// horizontal plane
+fn in_coverage(radar_receiver: &RadarReceiver, vector: &TargetVector) -> bool {
+    if radar_receiver.fov >= 360.0 || vector.mag == 0 {
+        return true;
+    }
+    if radar_receiver.sweep_rate == 0.0 {
+        return in_beam(radar_receiver, 0, vector);
+    }
+    (vector.elevation - 90.0).abs() <= radar_receiver.fov / 2.0
+}
+
+/// How far away the receiver can detect a signature of the given size
+fn detection_range(radar_receiver: &RadarReceiver, signature: f64) -> f64 {
+    radar_receiver.radius
+        * (signature * radar_receiver.sensitivity * beam_gain(radar_receiver) / DETECTION_THRESHOLD)
+            .max(0.0)
+            .powf(0.25)
+}
+
+/// Determines whether the receiver detects a signature at the target's position and, if so, with
+/// what confidence: 0.0 right at the detection threshold, up to 1.0 at `CLEAR_SNR`
+fn detect(
+    entity: &Position,
+    target: &Position,
+    radar_receiver: &RadarReceiver,
+    signature: f64,
+) -> Option<f64> {
+    if !within_radius(entity, target, detection_range(radar_receiver, signature)) {
+        return None;
+    }
+    let distance = entity.distance_to_3d(target);
+    if distance <= 0.0 {
+        return Some(1.0);
+    }
+    let snr = signature
+        * radar_receiver.sensitivity
+        * beam_gain(radar_receiver)
+        * (radar_receiver.radius / distance).powi(4);
+    Some(
+        ((snr / DETECTION_THRESHOLD).ln() / (CLEAR_SNR / DETECTION_THRESHOLD).ln()).clamp(0.0, 1.0),
+    )
+}
+
+/// How fast the target closes in on the observer in KPH (negative when it's moving away), and
+/// in how many seconds it will be nearest, if it's still getting closer
+fn closing(
+    observer: &Position,
+    observer_velocity: &Velocity,
+    target: &Position,
+    target_velocity: &Velocity,
+) -> (f64, Option<f64>) {
+    let (ox, oy, oz) = observer_velocity.components();
+    let (tx, ty, tz) = target_velocity.components();
+    let relative = (tx - ox, ty - oy, tz - oz);
+    let offset = (
+        target.x - observer.x,
+        target.y - observer.y,
+        target.z - observer.z,
+    );
+    let distance = observer.distance_to_3d(target);
+    let approach = -(offset.0 * relative.0 + offset.1 * relative.1 + offset.2 * relative.2);
+    let speed_squared = relative.0.powi(2) + relative.1.powi(2) + relative.2.powi(2);
+    let closing_speed = if distance > 0.0 {
+        approach / distance
+    } else {
+        0.0
+    };
+    let closest_approach_secs = if approach > 0.0 && speed_squared > 0.0 {
+        Some(approach / speed_squared * 3600.0)
+    } else {
+        None
+    };
+    (closing_speed, closest_approach_secs)
+}
+
 /// Helper function format a `radar_transponder` ResourceIdentifier given a specific entity
 fn transponder_for_entity(shard: &str, entity_id: &str) -> ResourceIdentifier {
     ResourceIdentifier {
@@ -287,14 +1046,29 @@ fn transponder_for_entity(shard: &str, entity_id: &str) -> ResourceIdentifier {
 
 #[cfg(test)]
 mod test {
+    use super::changed_values;
+    use super::closing;
+    use super::detect;
+    use super::disguise;
+    use super::in_beam;
+    use super::in_coverage;
     use super::radar_updates;
+    use super::resolve_contacts;
+    use super::sightings_after;
     use super::within_radius;
+    use super::Beacon;
+    use super::ContactHandles;
     use super::HashMap;
     use super::Position;
+    use super::Profile;
     use super::RadarContact;
     use super::RadarContactDelta;
     use super::RadarReceiver;
+    use super::RadarTransponder;
+    use super::Relay;
     use super::ResourceIdentifier;
+    use super::ShardCache;
+    use super::Velocity;
 
     #[test]
     fn test_within_radius() {
@@ -344,6 +1118,89 @@ mod test {
         assert!(within_radius(&a, &b, radius));
     }
 
+    #[test]
+    fn test_detection() {
+        let origin = Position::new(0.0, 0.0, 0.0);
+        let receiver = RadarReceiver {
+            radius: 20.0,
+            ..Default::default()
+        };
+
+        // A plain signature is detected up to the radius, clearly at half of it
+        assert_eq!(
+            Some(1.0),
+            detect(&origin, &Position::new(10.0, 0.0, 0.0), &receiver, 1.0)
+        );
+        let faint = detect(&origin, &Position::new(19.0, 0.0, 0.0), &receiver, 1.0).unwrap();
+        assert!(faint > 0.0 && faint < 0.2);
+        assert_eq!(
+            None,
+            detect(&origin, &Position::new(21.0, 0.0, 0.0), &receiver, 1.0)
+        );
+
+        // Stealth shrinks the signature, sensitivity makes up for it
+        assert_eq!(
+            None,
+            detect(&origin, &Position::new(15.0, 0.0, 0.0), &receiver, 0.1)
+        );
+        let sensitive = RadarReceiver {
+            radius: 20.0,
+            sensitivity: 10.0,
+            ..Default::default()
+        };
+        assert!(detect(&origin, &Position::new(15.0, 0.0, 0.0), &sensitive, 0.1).is_some());
+    }
+
+    #[test]
+    fn test_sweep() {
+        let origin = Position::new(0.0, 0.0, 0.0);
+        let east = origin.vector_to(&Position::new(10.0, 0.0, 0.0));
+        let north = origin.vector_to(&Position::new(0.0, 10.0, 0.0));
+        let above = origin.vector_to(&Position::new(10.0, 0.0, 10.0));
+        let receiver = RadarReceiver {
+            radius: 20.0,
+            fov: 30.0,
+            sweep_rate: 45.0,
+            ..Default::default()
+        };
+
+        // The beam turns from 0 to 45 degrees during frame 1, and from 45 to 90 during frame 2
+        assert!(in_beam(&receiver, 1, &east));
+        assert!(!in_beam(&receiver, 1, &north));
+        assert!(in_beam(&receiver, 2, &north));
+        assert!(!in_beam(&receiver, 3, &east));
+        // 45 degrees above the horizon is outside a 30 degree cone
+        assert!(!in_beam(&receiver, 1, &above));
+        assert!(in_beam(&RadarReceiver::default(), 3, &above));
+        // The turning beam comes round to the north, but never reaches that high
+        assert!(in_coverage(&receiver, &north));
+        assert!(!in_coverage(&receiver, &above));
+        // A fixed beam only ever covers its own cone
+        let fixed = RadarReceiver {
+            radius: 20.0,
+            fov: 30.0,
+            ..Default::default()
+        };
+        assert!(in_coverage(&fixed, &east));
+        assert!(!in_coverage(&fixed, &north));
+
+        // A narrow beam reaches further than an omnidirectional one
+        let far = Position::new(30.0, 0.0, 0.0);
+        assert_eq!(
+            None,
+            detect(
+                &origin,
+                &far,
+                &RadarReceiver {
+                    radius: 20.0,
+                    ..Default::default()
+                },
+                1.0
+            )
+        );
+        assert!(detect(&origin, &far, &receiver, 1.0).is_some());
+    }
+
     #[test]
     fn test_add_contacts() {
         let rid = "decs.components.the_shard.myownentity".to_string();
@@ -352,7 +1209,10 @@ mod test {
             y: 0.0,
             z: 0.0,
         };
-        let radar_receiver = RadarReceiver { radius: 5.0 };
+        let radar_receiver = RadarReceiver {
+            radius: 5.0,
+            ..Default::default()
+        };
         let old_contacts: HashMap<String, RadarContact> = HashMap::new();
         let mut all_positions: HashMap<String, Position> = HashMap::new();
 
@@ -364,9 +1224,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.asteroid.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let nearby_ship = RadarContact {
             entity_id: "decs.components.the_shard.ship".to_string(),
@@ -374,9 +1236,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.ship.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let mut far_away_money = RadarContact {
             entity_id: "decs.components.the_shard.money".to_string(),
@@ -384,9 +1248,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.money.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let far_away_money_pos = Position {
             x: 500.0,
@@ -407,10 +1273,12 @@ mod test {
             &rid,
             "the_shard",
             &current_position,
+            &Velocity::default(),
             &radar_receiver,
             &old_contacts,
             &all_positions,
-            None,
+            &HashMap::new(),
+            0,
         );
 
         assert_eq!(changes.len(), 2);
@@ -440,7 +1308,10 @@ mod test {
             y: 0.0,
             z: 0.0,
         };
-        let radar_receiver = RadarReceiver { radius: 5.0 };
+        let radar_receiver = RadarReceiver {
+            radius: 5.0,
+            ..Default::default()
+        };
         let mut all_positions: HashMap<String, Position> = HashMap::new();
 
         let vector_to = current_position.vector_to(&current_position);
@@ -452,9 +1323,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.asteroid.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let nearby_entity = "decs.components.the_shard.ship";
         let nearby_ship = RadarContact {
@@ -463,9 +1336,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.ship.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let faraway_entity = "decs.components.the_shard.money";
         let far_away_money = RadarContact {
@@ -474,9 +1349,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.money.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
 
         let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
@@ -507,10 +1384,12 @@ mod test {
             &rid,
             "the_shard",
             &current_position,
+            &Velocity::default(),
             &radar_receiver,
             &old_contacts,
             &all_positions,
-            None,
+            &HashMap::new(),
+            0,
         );
 
         assert_eq!(changes.len(), 2);
@@ -537,7 +1416,10 @@ mod test {
             y: 0.0,
             z: 0.0,
         };
-        let radar_receiver = RadarReceiver { radius: 5.0 };
+        let radar_receiver = RadarReceiver {
+            radius: 5.0,
+            ..Default::default()
+        };
         let mut all_positions: HashMap<String, Position> = HashMap::new();
 
         let vector_to = current_position.vector_to(&current_position);
@@ -549,9 +1431,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.asteroid.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let nearby_entity_id = "decs.components.the_shard.ship";
         let nearby_ship = RadarContact {
@@ -560,9 +1444,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.ship.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let faraway_entity_id = "decs.components.the_shard.money";
         let far_away_money = RadarContact {
@@ -571,9 +1457,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.money.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
 
         let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
@@ -600,10 +1488,12 @@ mod test {
             &rid,
             "the_shard",
             &current_position,
+            &Velocity::default(),
             &radar_receiver,
             &old_contacts,
             &all_positions,
-            None,
+            &HashMap::new(),
+            0,
         );
 
         assert_eq!(changes.len(), 3);
@@ -626,7 +1516,10 @@ mod test {
             y: 0.0,
             z: 0.0,
         };
-        let radar_receiver = RadarReceiver { radius: 5.0 };
+        let radar_receiver = RadarReceiver {
+            radius: 5.0,
+            ..Default::default()
+        };
         let mut all_positions: HashMap<String, Position> = HashMap::new();
 
         let vector_to = current_position.vector_to(&current_position);
@@ -638,9 +1531,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.asteroid.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let nearby_entity_id = "decs.components.the_shard.ship";
         let mut nearby_ship = RadarContact {
@@ -649,9 +1544,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.ship.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
         let faraway_entity_id = "decs.components.the_shard.money";
         let far_away_money = RadarContact {
@@ -660,9 +1557,11 @@ mod test {
             distance_xy: vector_to.distance_xy,
             azimuth: vector_to.azimuth,
             elevation: vector_to.elevation,
-            transponder: ResourceIdentifier {
+            transponder: Some(ResourceIdentifier {
                 rid: "decs.components.the_shard.money.transponder".to_string(),
-            },
+            }),
+            confidence: 1.0,
+            ..Default::default()
         };
 
         let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
@@ -707,10 +1606,12 @@ mod test {
             &rid,
             "the_shard",
             &current_position,
+            &Velocity::default(),
             &radar_receiver,
             &old_contacts,
             &all_positions,
-            None,
+            &HashMap::new(),
+            0,
         );
 
         assert_eq!(changes.len(), 3);
@@ -724,4 +1625,630 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_remove_deleted_contact() {
+        let rid = "decs.components.the_shard.myownentity".to_string();
+        let current_position = Position {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let radar_receiver = RadarReceiver {
+            radius: 5.0,
+            ..Default::default()
+        };
+        let deleted_ship = RadarContact {
+            entity_id: "decs.components.the_shard.ship".to_string(),
+            transponder: Some(ResourceIdentifier {
+                rid: "decs.components.the_shard.ship.transponder".to_string(),
+            }),
+            ..Default::default()
+        };
+        let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
+        let remove_rid = "decs.components.the_shard.myownentity.1".to_string();
+        old_contacts.insert(remove_rid.clone(), deleted_ship);
+        let mut all_positions: HashMap<String, Position> = HashMap::new();
+        all_positions.insert(rid.to_string(), current_position);
+
+        let changes = radar_updates(
+            &rid,
+            "the_shard",
+            &current_position,
+            &Velocity::default(),
+            &radar_receiver,
+            &old_contacts,
+            &all_positions,
+            &HashMap::new(),
+            0,
+        );
+
+        assert_eq!(vec![RadarContactDelta::Remove(remove_rid)], changes);
+    }
+
+    #[test]
+    fn test_unidentified_contacts() {
+        let rid = "decs.components.the_shard.myownentity".to_string();
+        let current_position = Position::new(0.0, 0.0, 0.0);
+        let radar_receiver = RadarReceiver {
+            radius: 20.0,
+            identify_range: 5.0,
+            ..Default::default()
+        };
+        let mut all_positions: HashMap<String, Position> = HashMap::new();
+        all_positions.insert("broadcasting".to_string(), Position::new(10.0, 0.0, 0.0));
+        all_positions.insert("dark".to_string(), Position::new(10.0, 0.0, 0.0));
+        all_positions.insert("dark_close".to_string(), Position::new(3.0, 0.0, 0.0));
+        all_positions.insert("derelict".to_string(), Position::new(3.0, 0.0, 0.0));
+        let profile = |active: Option<bool>| Profile {
+            transponder: active.map(|active| RadarTransponder {
+                active,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut profiles: HashMap<String, Profile> = HashMap::new();
+        profiles.insert("broadcasting".to_string(), profile(Some(true)));
+        profiles.insert("dark".to_string(), profile(Some(false)));
+        profiles.insert("dark_close".to_string(), profile(Some(false)));
+        profiles.insert("derelict".to_string(), profile(None));
+
+        let mut identified: Vec<(String, bool)> = radar_updates(
+            &rid,
+            "the_shard",
+            &current_position,
+            &Velocity::default(),
+            &radar_receiver,
+            &HashMap::new(),
+            &all_positions,
+            &profiles,
+            0,
+        )
+        .into_iter()
+        .map(|delta| match delta {
+            RadarContactDelta::Add(rc) => (rc.entity_id, rc.transponder.is_some()),
+            other => panic!("Unexpected delta {:?}", other),
+        })
+        .collect();
+        identified.sort();
+
+        assert_eq!(
+            vec![
+                ("broadcasting".to_string(), true),
+                ("dark".to_string(), false),
+                ("dark_close".to_string(), true),
+                ("derelict".to_string(), false),
+            ],
+            identified
+        );
+    }
+
+    #[test]
+    fn test_closing() {
+        let origin = Position::new(0.0, 0.0, 0.0);
+        let ahead = Position::new(100.0, 0.0, 0.0);
+        let still = Velocity::default();
+        let east = Velocity::new(100, 1.0, 0.0, 0.0);
+        let west = Velocity::new(100, -1.0, 0.0, 0.0);
+
+        // Heading straight for each other, they meet in half an hour
+        assert_eq!(
+            (200.0, Some(1800.0)),
+            closing(&origin, &east, &ahead, &west)
+        );
+        // Moving away, there's no closest approach to come
+        assert_eq!((-100.0, None), closing(&origin, &still, &ahead, &east));
+        // Flying in formation, nothing changes
+        assert_eq!((0.0, None), closing(&origin, &east, &ahead, &east));
+
+        // Passing by to the side: not closing in at all at the closest point
+        let abeam = Position::new(0.0, 50.0, 0.0);
+        assert_eq!((0.0, None), closing(&origin, &still, &abeam, &east));
+        let (speed, tca) = closing(&origin, &still, &Position::new(-100.0, 50.0, 0.0), &east);
+        assert!(speed > 0.0);
+        assert_eq!(Some(3600.0), tca);
+    }
+
+    #[test]
+    fn test_filter_contacts() {
+        let rid = "decs.components.the_shard.myownentity".to_string();
+        let current_position = Position::new(0.0, 0.0, 0.0);
+        let mut all_positions: HashMap<String, Position> = HashMap::new();
+        let mut profiles: HashMap<String, Profile> = HashMap::new();
+        for (entity, x, object_type, display_name) in [
+            ("asteroid", 2.0, "asteroid", "Rock"),
+            ("depleted", 3.0, "asteroid", "Rock (depleted)"),
+            ("near_ship", 4.0, "ship", "Near"),
+            ("far_ship", 8.0, "ship", "Far"),
+        ] {
+            all_positions.insert(entity.to_string(), Position::new(x, 0.0, 0.0));
+            profiles.insert(
+                entity.to_string(),
+                Profile {
+                    transponder: Some(RadarTransponder {
+                        object_type: object_type.to_string(),
+                        display_name: display_name.to_string(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            );
+        }
+        let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
+        old_contacts.insert(
+            "decs.components.the_shard.myownentity.radar_contacts.1".to_string(),
+            RadarContact {
+                entity_id: "far_ship".to_string(),
+                ..Default::default()
+            },
+        );
+        let updates = |radar_receiver: &RadarReceiver| {
+            let mut entities: Vec<String> = radar_updates(
+                &rid,
+                "the_shard",
+                &current_position,
+                &Velocity::default(),
+                radar_receiver,
+                &old_contacts,
+                &all_positions,
+                &profiles,
+                0,
+            )
+            .into_iter()
+            .map(|delta| match delta {
+                RadarContactDelta::Add(rc) => format!("add {}", rc.entity_id),
+                RadarContactDelta::Change(_rid, rc) => format!("change {}", rc.entity_id),
+                RadarContactDelta::Remove(rid) => format!("remove {}", rid),
+            })
+            .collect();
+            entities.sort();
+            entities
+        };
+
+        let hide_depleted = RadarReceiver {
+            radius: 20.0,
+            hide_depleted: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["add asteroid", "add near_ship", "change far_ship"],
+            updates(&hide_depleted)
+        );
+
+        // Only the nearest ship makes the cut, so the far one is dropped
+        let nearest_ship = RadarReceiver {
+            radius: 20.0,
+            object_types: vec!["ship".to_string()],
+            max_contacts: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "add near_ship",
+                "remove decs.components.the_shard.myownentity.radar_contacts.1"
+            ],
+            updates(&nearest_ship)
+        );
+    }
+
+    #[test]
+    fn test_changed_values() {
+        let old = RadarContact {
+            entity_id: "ship".to_string(),
+            distance: 10,
+            azimuth: 45.0,
+            transponder: Some(ResourceIdentifier {
+                rid: "decs.components.the_shard.ship.transponder".to_string(),
+            }),
+            confidence: 1.0,
+            ..Default::default()
+        };
+        let new = RadarContact {
+            distance: 9,
+            transponder: None,
+            ..old.clone()
+        };
+
+        let values = changed_values(&old, &new).unwrap();
+        assert_eq!(2, values.len());
+        assert_eq!(Some(&serde_json::json!(9)), values.get("distance"));
+        assert_eq!(Some(&serde_json::Value::Null), values.get("transponder"));
+        assert!(changed_values(&new, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_beacons() {
+        let rid = "decs.components.the_shard.myownentity".to_string();
+        let current_position = Position::new(0.0, 0.0, 0.0);
+        let radar_receiver = RadarReceiver {
+            radius: 20.0,
+            ..Default::default()
+        };
+        let mut all_positions: HashMap<String, Position> = HashMap::new();
+        let mut profiles: HashMap<String, Profile> = HashMap::new();
+        for (entity, x, range) in [
+            ("starbase", 10_000.0, None),
+            ("jump_gate", 500.0, Some(1_000.0)),
+            ("mission_marker", 5_000.0, Some(1_000.0)),
+        ] {
+            all_positions.insert(entity.to_string(), Position::new(x, 0.0, 0.0));
+            profiles.insert(
+                entity.to_string(),
+                Profile {
+                    beacon: Some(Beacon { range }),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut visible: Vec<String> = radar_updates(
+            &rid,
+            "the_shard",
+            &current_position,
+            &Velocity::default(),
+            &radar_receiver,
+            &HashMap::new(),
+            &all_positions,
+            &profiles,
+            0,
+        )
+        .into_iter()
+        .map(|delta| match delta {
+            RadarContactDelta::Add(rc) => {
+                assert_eq!(1.0, rc.confidence);
+                rc.entity_id
+            }
+            other => panic!("Unexpected delta {:?}", other),
+        })
+        .collect();
+        visible.sort();
+
+        assert_eq!(vec!["jump_gate", "starbase"], visible);
+
+        // A beacon never lists itself, and beacons don't count towards the cap on contacts
+        all_positions.insert("myownentity".to_string(), current_position);
+        profiles.insert(
+            "myownentity".to_string(),
+            Profile {
+                beacon: Some(Beacon { range: None }),
+                ..Default::default()
+            },
+        );
+        for (entity, x) in [("near", 5.0), ("far", 10.0)] {
+            all_positions.insert(entity.to_string(), Position::new(x, 0.0, 0.0));
+        }
+        let capped = RadarReceiver {
+            radius: 20.0,
+            max_contacts: Some(1),
+            ..Default::default()
+        };
+        let mut visible: Vec<String> = radar_updates(
+            "myownentity",
+            "the_shard",
+            &current_position,
+            &Velocity::default(),
+            &capped,
+            &HashMap::new(),
+            &all_positions,
+            &profiles,
+            0,
+        )
+        .into_iter()
+        .map(|delta| match delta {
+            RadarContactDelta::Add(rc) => rc.entity_id,
+            other => panic!("Unexpected delta {:?}", other),
+        })
+        .collect();
+        visible.sort();
+
+        assert_eq!(vec!["jump_gate", "near", "starbase"], visible);
+    }
+
+    #[test]
+    fn test_starbase_beacons() {
+        let mut cache = ShardCache::default();
+        for (entity, object_type) in [("old_starbase", "starbase"), ("ship", "ship")] {
+            cache.transponders.insert(
+                entity.to_string(),
+                RadarTransponder {
+                    object_type: object_type.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        cache.beacons.insert(
+            "jump_gate".to_string(),
+            Beacon {
+                range: Some(1_000.0),
+            },
+        );
+
+        // A starbase without a beacon component is still visible from anywhere
+        assert_eq!(
+            Some(Beacon { range: None }),
+            cache.profile("old_starbase").beacon
+        );
+        assert_eq!(None, cache.profile("ship").beacon);
+        assert_eq!(
+            Some(Beacon {
+                range: Some(1_000.0)
+            }),
+            cache.profile("jump_gate").beacon
+        );
+
+        let mut beacons = cache.beacon_entities();
+        beacons.sort();
+        assert_eq!(vec!["jump_gate", "old_starbase"], beacons);
+    }
+
+    #[test]
+    fn test_contacts_out_of_beam() {
+        let rid = "decs.components.the_shard.myownentity".to_string();
+        let current_position = Position::new(0.0, 0.0, 0.0);
+        let turning = RadarReceiver {
+            radius: 20.0,
+            fov: 30.0,
+            sweep_rate: 45.0,
+            ..Default::default()
+        };
+        let fixed = RadarReceiver {
+            radius: 20.0,
+            fov: 30.0,
+            ..Default::default()
+        };
+        let mut all_positions: HashMap<String, Position> = HashMap::new();
+        let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
+        for (i, (entity, position)) in [
+            ("north", Position::new(0.0, 10.0, 0.0)),
+            ("above", Position::new(10.0, 0.0, 10.0)),
+        ]
+        .iter()
+        .enumerate()
+        {
+            all_positions.insert(entity.to_string(), *position);
+            old_contacts.insert(
+                format!("decs.components.the_shard.myownentity.radar_contacts.{}", i),
+                RadarContact {
+                    entity_id: entity.to_string(),
+                    ..Default::default()
+                },
+            );
+        }
+        let removed = |radar_receiver: &RadarReceiver| {
+            let mut removed: Vec<String> = radar_updates(
+                &rid,
+                "the_shard",
+                &current_position,
+                &Velocity::default(),
+                radar_receiver,
+                &old_contacts,
+                &all_positions,
+                &HashMap::new(),
+                1,
+            )
+            .into_iter()
+            .map(|delta| match delta {
+                RadarContactDelta::Remove(rid) => rid,
+                other => panic!("Unexpected delta {:?}", other),
+            })
+            .collect();
+            removed.sort();
+            removed
+        };
+
+        // Neither is swept during frame 1. The turning beam will come round to the contact to
+        // the north, but never to the one above it
+        assert_eq!(
+            vec!["decs.components.the_shard.myownentity.radar_contacts.1"],
+            removed(&turning)
+        );
+        // A fixed beam will never sweep over either of them
+        assert_eq!(
+            vec![
+                "decs.components.the_shard.myownentity.radar_contacts.0",
+                "decs.components.the_shard.myownentity.radar_contacts.1"
+            ],
+            removed(&fixed)
+        );
+    }
+
+    #[test]
+    fn test_sensor_network() {
+        let mut cache = ShardCache::default();
+        for entity in ["flagship", "scout", "stowaway"] {
+            cache
+                .links
+                .insert(entity.to_string(), "flagship".to_string());
+        }
+        cache.links.insert("loner".to_string(), "loner".to_string());
+        cache
+            .fleets
+            .insert("flagship".to_string(), vec!["scout".to_string()]);
+
+        // Naming a network doesn't join it until the flagship takes the entity into its fleet
+        assert_eq!(vec!["scout"], cache.linked("flagship"));
+        assert_eq!(vec!["flagship"], cache.linked("scout"));
+        assert!(cache.linked("stowaway").is_empty());
+        assert!(cache.linked("loner").is_empty());
+
+        cache
+            .fleets
+            .get_mut("flagship")
+            .unwrap()
+            .push("stowaway".to_string());
+        let mut linked = cache.linked("stowaway");
+        linked.sort();
+        assert_eq!(vec!["flagship", "scout"], linked);
+    }
+
+    #[test]
+    fn test_relayed_contacts() {
+        let rid = "decs.components.the_shard.myownentity".to_string();
+        let current_position = Position::new(0.0, 0.0, 0.0);
+        let radar_receiver = RadarReceiver {
+            radius: 20.0,
+            ..Default::default()
+        };
+        let relay = Some(Relay {
+            confidence: 0.8,
+            identified: true,
+        });
+        let mut all_positions: HashMap<String, Position> = HashMap::new();
+        let mut profiles: HashMap<String, Profile> = HashMap::new();
+        for (entity, x, relay) in [
+            ("seen", 10.0, None),
+            ("seen_by_both", 5.0, relay),
+            ("scouted", 100.0, relay),
+            ("lost", 100.0, None),
+        ] {
+            all_positions.insert(entity.to_string(), Position::new(x, 0.0, 0.0));
+            profiles.insert(
+                entity.to_string(),
+                Profile {
+                    transponder: Some(RadarTransponder {
+                        active: false,
+                        ..Default::default()
+                    }),
+                    relay,
+                    ..Default::default()
+                },
+            );
+        }
+        let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
+        for (i, entity) in ["seen_by_both", "lost"].iter().enumerate() {
+            old_contacts.insert(
+                format!("decs.components.the_shard.myownentity.radar_contacts.{}", i),
+                RadarContact {
+                    entity_id: entity.to_string(),
+                    relayed: true,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut changes: Vec<String> = radar_updates(
+            &rid,
+            "the_shard",
+            &current_position,
+            &Velocity::default(),
+            &radar_receiver,
+            &old_contacts,
+            &all_positions,
+            &profiles,
+            0,
+        )
+        .into_iter()
+        .map(|delta| match delta {
+            RadarContactDelta::Add(rc) | RadarContactDelta::Change(_, rc) => format!(
+                "{} relayed={} identified={}",
+                rc.entity_id,
+                rc.relayed,
+                rc.transponder.is_some()
+            ),
+            RadarContactDelta::Remove(rid) => format!("remove {}", rid),
+        })
+        .collect();
+        changes.sort();
+
+        assert_eq!(
+            vec![
+                "remove decs.components.the_shard.myownentity.radar_contacts.1",
+                "scouted relayed=true identified=true",
+                "seen relayed=false identified=false",
+                "seen_by_both relayed=false identified=true",
+            ],
+            changes
+        );
+    }
+
+    #[test]
+    fn test_sightings_after() {
+        let contact = |entity: &str, confidence: f64, relayed: bool| RadarContact {
+            entity_id: entity.to_string(),
+            confidence,
+            relayed,
+            ..Default::default()
+        };
+        let mut old_contacts: HashMap<String, RadarContact> = HashMap::new();
+        for (i, rc) in [
+            contact("kept", 0.5, false),
+            contact("changed", 0.2, false),
+            contact("removed", 0.9, false),
+            contact("relayed", 0.9, true),
+        ]
+        .iter()
+        .enumerate()
+        {
+            old_contacts.insert(
+                format!("decs:components:the_shard:myownentity:radar_contacts:{}", i),
+                rc.clone(),
+            );
+        }
+        let updates = vec![
+            RadarContactDelta::Change(
+                "decs.components.the_shard.myownentity.radar_contacts.1".to_string(),
+                RadarContact {
+                    transponder: Some(Default::default()),
+                    ..contact("changed", 0.7, false)
+                },
+            ),
+            RadarContactDelta::Remove(
+                "decs.components.the_shard.myownentity.radar_contacts.2".to_string(),
+            ),
+            RadarContactDelta::Add(contact("added", 1.0, false)),
+        ];
+
+        let seen = sightings_after(&old_contacts, &updates);
+        let mut entities: Vec<_> = seen.keys().cloned().collect();
+        entities.sort();
+        assert_eq!(vec!["added", "changed", "kept"], entities);
+        assert_eq!(0.7, seen["changed"].confidence);
+        assert!(seen["changed"].identified);
+        assert!(!seen["kept"].identified);
+    }
+
+    #[test]
+    fn test_disguise() {
+        let mut handles = ContactHandles::default();
+        let unknown = RadarContact {
+            entity_id: "alice".to_string(),
+            ..Default::default()
+        };
+        let known = RadarContact {
+            entity_id: "bob".to_string(),
+            transponder: Some(ResourceIdentifier {
+                rid: "decs.components.the_shard.bob.transponder".to_string(),
+            }),
+            ..Default::default()
+        };
+
+        // Only the unidentified contact is listed under a handle
+        let listed: HashMap<String, RadarContact> = vec![
+            (RadarContactDelta::Add(unknown.clone()), "1"),
+            (RadarContactDelta::Add(known.clone()), "2"),
+        ]
+        .into_iter()
+        .map(|(update, id)| match disguise(update, &mut handles) {
+            RadarContactDelta::Add(rc) => (id.to_string(), rc),
+            other => panic!("unexpected update {:?}", other),
+        })
+        .collect();
+        assert_ne!("alice", listed["1"].entity_id);
+        assert_eq!(known, listed["2"]);
+
+        // Radar sees the entities behind the handles again
+        let resolved = resolve_contacts(&listed, &handles);
+        assert_eq!(unknown, resolved["1"]);
+        assert_eq!(known, resolved["2"]);
+
+        // The same entity keeps its handle
+        let changed = disguise(
+            RadarContactDelta::Change("1".to_string(), unknown),
+            &mut handles,
+        );
+        assert_eq!(
+            RadarContactDelta::Change("1".to_string(), listed["1"].clone()),
+            changed
+        );
+    }
 }
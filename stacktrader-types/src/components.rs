@@ -1,5 +1,7 @@
 extern crate decscloud_common as decs;
 
+use std::collections::HashMap;
+
 const MS_PER_HOUR: f64 = 3_600_000.0;
 
 /// Represents the metadata and parameters for a given universe (the physical space
@@ -27,6 +29,48 @@ impl Default for UniverseMetadata {
     }
 }
 
+/// Represents the per-shard settings of the merchant system, stored as the `merchant` component
+/// on the shard's `universe` entity
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MerchantConfig {
+    pub trade_radius: f64, // How close (in km) an entity must be to a starbase to sell there
+}
+
+impl Default for MerchantConfig {
+    fn default() -> Self {
+        MerchantConfig { trade_radius: 5.0 }
+    }
+}
+
+/// Represents the per-shard settings of the leaderboard, stored as the `leaderboard` component on
+/// the shard's `universe` entity
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct LeaderboardConfig {
+    pub size: usize, // How many ranks the leaderboard collection shows
+    #[serde(default = "default_day_seconds", alias = "day_frames")]
+    pub day_seconds: u64, // How many seconds make up a day, so windows can be shortened for testing
+    #[serde(default = "default_season_days")]
+    pub season_days: u64,
+}
+
+fn default_day_seconds() -> u64 {
+    86_400
+}
+
+fn default_season_days() -> u64 {
+    28
+}
+
+impl Default for LeaderboardConfig {
+    fn default() -> Self {
+        LeaderboardConfig {
+            size: 10,
+            day_seconds: default_day_seconds(),
+            season_days: default_season_days(),
+        }
+    }
+}
+
 /// Represents a position in 3-dimensional space, assumed unit is Kilometers
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Copy)]
 pub struct Position {
@@ -109,6 +153,12 @@ impl Velocity {
     pub fn new(mag: u32, ux: f64, uy: f64, uz: f64) -> Self {
         Velocity { mag, ux, uy, uz }
     }
+
+    /// The velocity's components along each axis, in KPH
+    pub fn components(&self) -> (f64, f64, f64) {
+        let mag = f64::from(self.mag);
+        (mag * self.ux, mag * self.uy, mag * self.uz)
+    }
 }
 
 pub type Vector = Velocity;
@@ -127,15 +177,106 @@ pub struct TargetVector {
 /// Represents a selected target for the navigation system.
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Target {
-    pub rid: String, // The resource ID (e.g. decs.components.the_void.entity25) of the target
+    pub rid: String, // The resource ID (e.g. decs.components.the_void.entity25) of the target, or the handle of an unidentified radar contact (see `contacts`)
     pub eta_ms: f64, // Estimated time of arrival at the target, in milliseconds
     pub distance_km: f64, // Distance to the target in kilometers
 }
 
 /// Represents a radar component that scans for entities around the entity with the receiver.
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// An entity with a signature of 1.0 is detected up to `radius` away at a sensitivity of 1.0;
+/// more sensitive receivers pick up smaller signatures, or the same ones from further away.
+/// A receiver can also focus its beam into a cone of `fov` degrees that sweeps around the
+/// horizon at `sweep_rate` degrees per second, trading coverage for range
+/// Players can narrow down which contacts are listed by object type, and cap them to the nearest
+/// `max_contacts`
+#[derive(Serialize, Deserialize, Debug)]
 pub struct RadarReceiver {
     pub radius: f64, // The range of the radar as a radius in km
+    #[serde(default = "default_sensitivity")]
+    pub sensitivity: f64,
+    #[serde(default = "default_fov")]
+    pub fov: f64, // Width of the beam's cone in degrees, 360 being all around
+    #[serde(default)]
+    pub sweep_rate: f64, // Degrees per second the beam turns, 0 for a fixed beam
+    #[serde(default)]
+    pub azimuth: f64, // Azimuth in degrees the beam points at, or starts sweeping from
+    #[serde(default = "default_identify_range")]
+    pub identify_range: f64, // Range in km within which contacts with their transponder off are identified
+    #[serde(default)]
+    pub object_types: Vec<String>, // Only show contacts of these object types ("unknown" for unidentified ones), or all if empty
+    #[serde(default)]
+    pub hide_depleted: bool, // Whether to hide depleted asteroids
+    #[serde(default)]
+    pub max_contacts: Option<u32>, // Only keep this many of the nearest contacts, besides beacons
+}
+
+fn default_sensitivity() -> f64 {
+    1.0
+}
+
+fn default_fov() -> f64 {
+    360.0
+}
+
+fn default_identify_range() -> f64 {
+    5.0
+}
+
+impl Default for RadarReceiver {
+    fn default() -> Self {
+        RadarReceiver {
+            radius: 0.0,
+            sensitivity: default_sensitivity(),
+            fov: default_fov(),
+            sweep_rate: 0.0,
+            azimuth: 0.0,
+            identify_range: default_identify_range(),
+            object_types: Vec::new(),
+            hide_depleted: false,
+            max_contacts: None,
+        }
+    }
+}
+
+/// Represents how strongly an entity shows up on radar, stored as its `signature` component.
+/// Entities without one have a signature size of 1.0
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RadarSignature {
+    pub size: f64,
+}
+
+/// Represents stealth equipment, stored as an entity's `stealth` component. It masks a fraction of
+/// the entity's radar signature, from 0.0 (none) to 1.0 (all of it)
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Stealth {
+    pub reduction: f64,
+}
+
+/// Marks an entity, such as a starbase, jump gate or mission marker, as a landmark that shows up
+/// on every radar no matter how far away it is, stored as its `beacon` component. A beacon with a
+/// `range` is only visible within that many km of it. A starbase without a `beacon` component is
+/// treated as a beacon with no `range`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Beacon {
+    #[serde(default)]
+    pub range: Option<f64>,
+}
+
+/// Links an entity's radar receiver into its fleet's sensor network, stored as its `sensor_link`
+/// component. `network` names the fleet's flagship, and the link only counts if the entity is the
+/// flagship itself or one of the members of the flagship's `fleet`, so it takes both the entity
+/// and the flagship to join a network. The receivers of every entity in the same network share the
+/// contacts they see directly with each other
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct SensorLink {
+    pub network: String,
+}
+
+/// The entities taken into a fleet, stored as the `fleet` component of its flagship
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Fleet {
+    #[serde(default)]
+    pub members: Vec<String>,
 }
 
 /// Represents a single radar contact
@@ -146,18 +287,79 @@ pub struct RadarContact {
     pub distance_xy: u32,
     pub azimuth: f64,
     pub elevation: f64,
-    pub transponder: decs::gateway::ResourceIdentifier,
+    #[serde(default)]
+    pub transponder: Option<decs::gateway::ResourceIdentifier>, // None for an unidentified contact
+    #[serde(default)]
+    pub confidence: f64, // From 0.0 (barely detected, a faint blip) to 1.0 (a clear one)
+    #[serde(default)]
+    pub velocity: Velocity, // The contact's own velocity
+    #[serde(default)]
+    pub closing_speed: f64, // Speed in KPH at which the contact approaches, negative when it recedes
+    #[serde(default)]
+    pub closest_approach_secs: Option<f64>, // Seconds until the contact is nearest, None once past it
+    #[serde(default)]
+    pub relayed: bool, // Whether the contact is relayed by a linked receiver rather than seen directly
 }
 
 /// Represents a transponder component for a radar contact that dictates how it should be displayed in the game UI
 /// object_type should be ["starbase" | "ship" | "asteroid"]
 /// display_name should be the name to display on the UI.
 /// color can either be in the form of a hex code `#ff0000` or a CSS recognized color `red` or `aliceblue`
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+/// active is whether the transponder is switched on. Switched off, the entity shows up on radar as
+/// an unknown contact until it comes within the receiver's `identify_range`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct RadarTransponder {
     pub object_type: String,
     pub display_name: String,
     pub color: String,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl RadarTransponder {
+    /// Mining marks an asteroid as depleted by appending `(depleted)` to its display name
+    pub fn is_depleted(&self) -> bool {
+        self.display_name.ends_with("(depleted)")
+    }
+}
+
+impl Default for RadarTransponder {
+    fn default() -> Self {
+        RadarTransponder {
+            object_type: String::new(),
+            display_name: String::new(),
+            color: String::new(),
+            active: default_active(),
+        }
+    }
+}
+
+/// Represents a player's public profile, stored as the player's `profile` component. When present,
+/// its `display_name` is shown instead of the transponder's (e.g. on the leaderboard)
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct PlayerProfile {
+    pub display_name: String,
+}
+
+/// Represents a starbase at which entities can sell their stacks. `prices` holds a multiplier per
+/// stack type that is applied to the merchant's base value for that stack. Stack types that are
+/// not listed sell at the base value
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Starbase {
+    pub name: String,
+    #[serde(default)]
+    pub prices: HashMap<String, f64>,
+}
+
+impl Starbase {
+    /// The multiplier this starbase applies to the base value of the given stack type
+    pub fn price_multiplier(&self, stack_type: &str) -> f64 {
+        self.prices.get(stack_type).cloned().unwrap_or(1.0)
+    }
 }
 
 // At this point in the game development, mining resources are the only things that can be
@@ -168,20 +370,202 @@ pub struct MiningResource {
     pub qty: u32,           // Quantity of stack item in the resource
 }
 
+/// Represents an item in a player's `inventory` or `sell_list`. The stack itself is identical to
+/// the `MiningResource` it was extracted from. `error` is attached by a system that refused to
+/// process the item (e.g. a rejected sale) so the UI can tell the player why
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct InventoryItem {
+    #[serde(flatten)]
+    pub resource: MiningResource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
 pub struct MiningExtractor {
     pub target: String, // Fully-qualified ID of the mining resource component to which extractor is attached
     pub remaining_ms: f64, // Time remaining for extraction
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+/// Amount of in-game currency. Wallets were originally 32-bit, which a long-running shard can
+/// overflow; a 64-bit amount still deserializes every wallet stored before the change
+pub type Credits = i64;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
 pub struct CreditWallet {
-    pub credits: i32,
+    pub credits: Credits,
+}
+
+impl CreditWallet {
+    /// Produces a wallet with the given amount added (or removed, if negative). Returns `None`
+    /// instead of wrapping if the new balance would overflow
+    pub fn checked_add(&self, amount: Credits) -> Option<CreditWallet> {
+        self.credits
+            .checked_add(amount)
+            .map(|credits| CreditWallet { credits })
+    }
+}
+
+/// Represents a single entry in an entity's append-only `ledger` collection. Every system that
+/// moves credits in or out of a `wallet` posts one of these so a player's balance can be traced.
+/// A positive amount is a credit, a negative amount is a debit
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct LedgerEntry {
+    pub txid: String, // Unique ID of the transaction that produced this entry
+    // When the entry was posted, in milliseconds since the Unix epoch. Set when the entry is
+    // published; entries posted before it existed carry the frame number in its place
+    #[serde(default)]
+    pub timestamp: u64,
+    // Sequence number of the game loop frame in which the entry was posted, which orders entries
+    // posted during the same millisecond. It starts over whenever the game loop restarts
+    #[serde(default)]
+    pub seq_no: u64,
+    pub amount: Credits,      // Credits added to (or removed from) the wallet
+    pub balance: Credits,     // Wallet balance after the entry was applied
+    pub counterparty: String, // Who the credits came from or went to, e.g. `merchant` or an entity ID
+    pub item: Option<MiningResource>, // The stack that was traded, if any
+    pub reason: String,       // Human-readable reason for the entry, e.g. `sale`
+}
+
+/// Represents the lifecycle of a `TradeOffer`. The counterparty answers a `pending` offer with a
+/// `TradeResponse` that is `accepted` or `declined`; the trade system marks offers it could not
+/// carry out as `failed`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TradeStatus {
+    #[default]
+    Pending,
+    Accepted,
+    Declined,
+    Failed,
+}
+
+/// Represents an offer from one player to another, stored as the offering entity's `trade_offer`
+/// component. Items are referenced by the RIDs of the items in each player's `inventory`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct TradeOffer {
+    #[serde(default)]
+    pub id: String, // Assigned by the trade system when it first sees the offer
+    pub target: String, // Entity ID of the player the offer is made to
+    #[serde(default)]
+    pub offered_items: Vec<String>,
+    #[serde(default)]
+    pub offered_credits: Credits,
+    #[serde(default)]
+    pub requested_items: Vec<String>,
+    #[serde(default)]
+    pub requested_credits: Credits,
+    #[serde(default)]
+    pub status: TradeStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why a `failed` offer could not be carried out
+}
+
+/// Represents a player's answer to a `TradeOffer` made to them, stored as the answering entity's
+/// own `trade_response` component. The trade system only acts on an offer once the target's
+/// response names the offer's ID
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct TradeResponse {
+    pub offer: String,       // ID of the offer being answered
+    pub status: TradeStatus, // `accepted` or `declined`
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+/// Represents the lifecycle of a `MarketOrder`. Players post orders as `new` and may set an `open`
+/// order to `cancelled`; every other transition is made by the market system
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    #[default]
+    New,
+    Open,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+/// Represents a buy or sell order on a starbase market, stored in the posting entity's
+/// `market_orders` collection. A sell order offers an item from the entity's `inventory`, whose
+/// stack type and quantity the market fills in. A buy order names the stack type and quantity
+/// it wants. Either way `price` is per unit
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MarketOrder {
+    #[serde(default)]
+    pub id: String, // Assigned by the market system when the order is accepted onto the book
+    pub side: OrderSide,
+    #[serde(default)]
+    pub stack_type: String,
+    #[serde(default)]
+    pub qty: u32, // Units still to be bought or sold
+    pub price: Credits,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<String>, // RID of the inventory item being sold
+    #[serde(default)]
+    pub status: OrderStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why the order was rejected
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractKind {
+    #[default]
+    Delivery, // Bring `qty` units of the stack type to the destination starbase
+    Mining, // Mine `qty` units of the stack type, anywhere
+}
+
+/// Represents the lifecycle of a `Contract`. A player asks for work by setting a `requested`
+/// contract; every other transition is made by the contracts system
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractStatus {
+    #[default]
+    Requested,
+    Active,
+    Completed,
+    Failed,
+    Expired,
+}
+
+/// Represents the contract a player is working on, stored as the player's `contract` component.
+/// The terms are drawn up by the contracts system at the starbase the player was docked at when
+/// the contract was requested. This component is only the player's copy: the contracts system
+/// keeps the contract as issued, and only ever reads the `id` back from this component
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Contract {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub kind: ContractKind,
+    #[serde(default)]
+    pub stack_type: String,
+    #[serde(default)]
+    pub qty: u32,
+    #[serde(default)]
+    pub destination: String, // Entity ID of the starbase awaiting a delivery, or that issued a mining contract
+    #[serde(default)]
+    pub destination_name: String,
+    #[serde(default)]
+    pub reward: Credits,
+    #[serde(default)]
+    pub remaining_ms: f64, // Time left before the contract expires
+    #[serde(default)]
+    pub progress: u32, // Units held towards a delivery, or mined, so far
+    #[serde(default)]
+    pub status: ContractStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Why the contract failed
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Position, Velocity};
+    use super::{CreditWallet, Position, Velocity};
 
     const FLOATEPSILON: f64 = std::f64::EPSILON;
     const PI: f64 = std::f64::consts::PI;
@@ -292,4 +676,19 @@ mod test {
         assert!((159.762 - v.azimuth) <= FLOATEPSILON);
         assert!((60.5169 - v.elevation) <= FLOATEPSILON);
     }
+
+    #[test]
+    fn legacy_wallet_checked_add() {
+        let wallet: CreditWallet = serde_json::from_str(r#"{"credits": 2147483647}"#).unwrap();
+
+        // Would have wrapped negative as an i32
+        let wallet = wallet.checked_add(100).unwrap();
+        assert_eq!(2_147_483_747, wallet.credits);
+
+        assert_eq!(None, wallet.checked_add(i64::MAX));
+        assert_eq!(
+            Some(CreditWallet { credits: 0 }),
+            wallet.checked_add(-2_147_483_747)
+        );
+    }
 }
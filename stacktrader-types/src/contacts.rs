@@ -0,0 +1,104 @@
+//! # Contact handles
+//!
+//! A radar contact that hasn't been identified must not give away which entity it is, so radar
+//! lists it under a handle rather than its entity ID. Handles are drawn by each receiver from its
+//! own counter and kept, along with the entities they stand for, in the KV store under
+//! `decs:{shard}:radar:{receiver}:handles`. A handle only means something to the receiver that
+//! drew it, and only for as long as the contact stays on its list.
+//!
+//! A player targets an unidentified contact through the RID
+//! `decs.{shard}.radar.{receiver}.{handle}`, which systems such as navigation resolve to the
+//! entity on the receiver's behalf with `resolve_target`.
+use guest::prelude::*;
+use std::collections::HashMap;
+
+/// The handles a receiver has given to the entities on its contact list
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct ContactHandles {
+    pub next: u64,                         // Number of the next handle to draw
+    pub entities: HashMap<String, String>, // Handle -> entity ID
+}
+
+impl ContactHandles {
+    /// The handle the entity is listed under, drawing a new one if it doesn't have one yet
+    pub fn handle_for(&mut self, entity: &str) -> String {
+        if let Some((handle, _)) = self.entities.iter().find(|(_, e)| *e == entity) {
+            return handle.clone();
+        }
+        self.next += 1;
+        let handle = format!("contact-{}", self.next);
+        self.entities.insert(handle.clone(), entity.to_string());
+        handle
+    }
+
+    /// The entity listed under the given handle
+    pub fn entity(&self, handle: &str) -> Option<&String> {
+        self.entities.get(handle)
+    }
+}
+
+fn handles_key(shard: &str, receiver: &str) -> String {
+    format!("decs:{}:radar:{}:handles", shard, receiver)
+}
+
+pub fn get_handles(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    receiver: &str,
+) -> std::result::Result<ContactHandles, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&handles_key(shard, receiver))? {
+        Some(s) => Ok(serde_json::from_str(&s)?),
+        None => Ok(ContactHandles::default()),
+    }
+}
+
+pub fn put_handles(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    receiver: &str,
+    handles: &ContactHandles,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().set(
+        &handles_key(shard, receiver),
+        &serde_json::to_string(handles)?,
+        None,
+    )?;
+    Ok(())
+}
+
+/// The shard and entity a player targets through the given RID: either an entity's own RID,
+/// `decs.components.{shard}.{entity}`, or a contact handle of the player's own receiver. Handles
+/// of other receivers, and handles that no longer stand for anything, resolve to nothing
+pub fn resolve_target(
+    ctx: &CapabilitiesContext,
+    entity: &str,
+    rid: &str,
+) -> std::result::Result<Option<(String, String)>, Box<dyn std::error::Error>> {
+    let tokens: Vec<&str> = rid.split('.').collect();
+    match tokens.as_slice() {
+        ["decs", "components", shard, target] => Ok(Some((shard.to_string(), target.to_string()))),
+        ["decs", shard, "radar", receiver, handle] if *receiver == entity => {
+            Ok(get_handles(ctx, shard, receiver)?
+                .entity(handle)
+                .map(|target| (shard.to_string(), target.clone())))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ContactHandles;
+
+    #[test]
+    fn test_handle_for() {
+        let mut handles = ContactHandles::default();
+        let rock = handles.handle_for("rock");
+        assert_eq!(rock, handles.handle_for("rock"));
+        let ship = handles.handle_for("alice");
+        assert_ne!(rock, ship);
+        assert!(!ship.contains("alice"));
+        assert_eq!(Some(&"alice".to_string()), handles.entity(&ship));
+        assert_eq!(None, handles.entity("alice"));
+    }
+}
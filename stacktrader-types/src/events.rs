@@ -0,0 +1,45 @@
+//! Events that systems publish about what players did, for other systems (e.g. the leaderboard)
+//! to keep statistics. They are plain NATS messages rather than component changes, published on
+//! `decs.stats.{shard}.{category}`
+use guest::prelude::*;
+
+pub const STAT_LIFETIME_CREDITS: &str = "lifetime_credits"; // Credits earned from sales and contracts
+pub const STAT_DISTANCE: &str = "distance"; // Meters travelled
+pub const STAT_ASTEROIDS_DEPLETED: &str = "asteroids_depleted";
+
+/// The statistic counting units mined of the given stack type, e.g. `mined_critical`
+pub fn stat_mined(stack_type: &str) -> String {
+    format!("mined_{}", stack_type)
+}
+
+/// The subject on which increments to the given statistic are published
+pub fn stat_subject(shard: &str, category: &str) -> String {
+    format!("decs.stats.{}.{}", shard, category)
+}
+
+/// Represents an increase in one of a player's statistics
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct StatIncrement {
+    pub entity_id: String,
+    pub amount: i64,
+}
+
+/// Publish an increase in one of the entity's statistics for the leaderboard
+pub fn publish_stat(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    category: &str,
+    amount: i64,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let stat = StatIncrement {
+        entity_id: entity.to_string(),
+        amount,
+    };
+    ctx.msg().publish(
+        &stat_subject(shard, category),
+        None,
+        &serde_json::to_vec(&stat)?,
+    )?;
+    Ok(())
+}
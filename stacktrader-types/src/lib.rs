@@ -1,4 +1,12 @@
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate lazy_static;
+extern crate waxosuit_guest as guest;
 
 pub mod components;
+pub mod contacts;
+pub mod events;
+pub mod starbase;
+pub mod transaction;
+pub mod wallet;
@@ -0,0 +1,178 @@
+//! # Starbases
+//!
+//! Sales, market orders and contracts are only honored near a starbase, i.e. within the trade
+//! radius of the shard's `MerchantConfig`. Starbases are the entities whose `transponder` has an
+//! `object_type` of `starbase`; their names and prices come from their `starbase` component.
+//! Starbases never move, so they are looked up once per shard and cached, as is the shard's
+//! `MerchantConfig`.
+use crate::components::*;
+use guest::prelude::*;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+const STARBASE_TYPE: &str = "starbase";
+const STARBASE: &str = "starbase";
+const MERCHANT: &str = "merchant";
+const POSITION: &str = "position";
+
+/// A cached starbase: its entity ID, where it is, and what it pays
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradingPost {
+    pub entity_id: String,
+    pub position: Position,
+    pub starbase: Starbase,
+}
+
+lazy_static! {
+    static ref STARBASES: RwLock<HashMap<String, Vec<TradingPost>>> = RwLock::new(HashMap::new());
+    static ref CONFIGS: RwLock<HashMap<String, MerchantConfig>> = RwLock::new(HashMap::new());
+}
+
+/// Find the starbase at which an entity at the given position would trade: the nearest starbase,
+/// provided that it is within the shard's trade radius
+pub fn trading_post(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    position: &Position,
+) -> std::result::Result<Option<TradingPost>, Box<dyn std::error::Error>> {
+    let radius = get_config(ctx, shard).trade_radius;
+    Ok(nearest_within(&get_starbases(ctx, shard)?, position, radius).cloned())
+}
+
+/// Find the starbase at which the given entity is docked, i.e. the one it would trade at from its
+/// current position. An entity without a position isn't docked anywhere
+pub fn docked_at(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<Option<TradingPost>, Box<dyn std::error::Error>> {
+    let key = format!("decs:components:{}:{}:{}", shard, entity, POSITION);
+    match ctx.kv().get(&key)? {
+        Some(s) => trading_post(ctx, shard, &serde_json::from_str(&s)?),
+        None => Ok(None),
+    }
+}
+
+fn nearest_within<'a>(
+    posts: &'a [TradingPost],
+    position: &Position,
+    radius: f64,
+) -> Option<&'a TradingPost> {
+    posts
+        .iter()
+        .map(|p| (position.distance_to_3d(&p.position), p))
+        .filter(|(d, _)| *d <= radius)
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(_, p)| p)
+}
+
+/// Retrieve the starbases from the cache, scanning the shard's transponders on a miss. An
+/// empty result is not cached so that starbases created after the first sale are still found
+pub fn get_starbases(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+) -> std::result::Result<Vec<TradingPost>, Box<dyn std::error::Error>> {
+    if let Some(starbases) = STARBASES.read().unwrap().get(shard) {
+        return Ok(starbases.clone());
+    }
+    let mut starbases = Vec::new();
+    let entities = ctx.kv().set_intersect(&[
+        format!("decs:{}:transponder:entities", shard),
+        format!("decs:{}:position:entities", shard),
+    ])?;
+    for entity in entities {
+        let transponder = ctx
+            .kv()
+            .get(&format!("decs:components:{}:{}:transponder", shard, entity))?
+            .and_then(|s| serde_json::from_str::<RadarTransponder>(&s).ok())
+            .filter(|t| t.object_type == STARBASE_TYPE);
+        if let Some(transponder) = transponder {
+            let position = ctx.kv().get(&format!(
+                "decs:components:{}:{}:{}",
+                shard, entity, POSITION
+            ))?;
+            // Starbases created before price tables existed have no `starbase` component,
+            // and simply pay the base value for everything
+            let starbase = match ctx.kv().get(&format!(
+                "decs:components:{}:{}:{}",
+                shard, entity, STARBASE
+            ))? {
+                Some(s) => serde_json::from_str(&s)?,
+                None => Starbase {
+                    name: transponder.display_name,
+                    prices: HashMap::new(),
+                },
+            };
+            if let Some(position) = position {
+                starbases.push(TradingPost {
+                    entity_id: entity,
+                    position: serde_json::from_str(&position)?,
+                    starbase,
+                });
+            }
+        }
+    }
+    if !starbases.is_empty() {
+        STARBASES
+            .write()
+            .unwrap()
+            .insert(shard.to_string(), starbases.clone());
+    }
+    Ok(starbases)
+}
+
+/// Retrieve the merchant configuration from the cache. If it's not in the cache, attempt
+/// to query it from the KV store. If it's not in there, use the default configuration without
+/// caching it, so a configuration that is set later on is still picked up
+pub fn get_config(ctx: &CapabilitiesContext, shard: &str) -> MerchantConfig {
+    if let Some(config) = CONFIGS.read().unwrap().get(shard) {
+        return config.clone();
+    }
+    match load_config(ctx, shard) {
+        Ok(Some(config)) => {
+            CONFIGS
+                .write()
+                .unwrap()
+                .insert(shard.to_string(), config.clone());
+            config
+        }
+        _ => MerchantConfig::default(),
+    }
+}
+
+fn load_config(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+) -> std::result::Result<Option<MerchantConfig>, Box<dyn std::error::Error>> {
+    let key = format!("decs:components:{}:universe:{}", shard, MERCHANT);
+    match ctx.kv().get(&key)? {
+        Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{nearest_within, Position, Starbase, TradingPost};
+
+    fn post(entity_id: &str, x: f64) -> TradingPost {
+        TradingPost {
+            entity_id: entity_id.to_string(),
+            position: Position::new(x, 0.0, 0.0),
+            starbase: Starbase::default(),
+        }
+    }
+
+    #[test]
+    fn test_nearest_within() {
+        let posts = vec![post("starbase_0", 0.0), post("starbase_1", 10.0)];
+
+        let near_alpha = nearest_within(&posts, &Position::new(4.0, 0.0, 0.0), 5.0);
+        assert_eq!("starbase_0", near_alpha.unwrap().entity_id);
+
+        let near_beta = nearest_within(&posts, &Position::new(6.0, 0.0, 0.0), 5.0);
+        assert_eq!("starbase_1", near_beta.unwrap().entity_id);
+
+        assert!(nearest_within(&posts, &Position::new(50.0, 0.0, 0.0), 5.0).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+//! # Transactions
+//!
+//! Systems that pay out credits identify each payment with a transaction ID that a retried frame
+//! or a second instance of the system will compute again, and claim that ID in the KV store
+//! before paying. A claim only marks the payment as in progress: the transaction is completed
+//! once the credit has been published, and released if it couldn't be, so that a failure part
+//! way through never causes a payment to be skipped. Claims and completion markers expire, so
+//! the KV store doesn't grow without bound. A payment made up of several steps can record each
+//! step as it is done, so a retry carries on from where the failed attempt left off.
+//!
+//! The same short-lived claims serve as locks on shared state, such as a wallet, that several
+//! systems read, modify and write back.
+use guest::prelude::*;
+
+/// How long a claimed transaction may remain unfinished before it can be retried, in seconds
+const CLAIM_TTL: u32 = 30;
+/// How many times a lock is attempted before giving up. Guests can't sleep, so there is no backoff
+/// in between: callers give up and let the next frame (or a redelivered message) try again
+pub const LOCK_ATTEMPTS: u32 = 10;
+/// How long a completed transaction is remembered, in seconds. This only has to outlive whatever
+/// triggered the payment, e.g. an item in a sell list, which is removed in the same frame unless
+/// that frame fails
+const COMPLETED_TTL: u32 = 24 * 60 * 60;
+
+/// The outcome of attempting to claim a transaction ID
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// This caller holds the claim and must either complete or release it
+    Won,
+    /// The transaction has already been paid
+    Completed,
+    /// Another caller holds the claim and has not finished yet
+    InFlight,
+}
+
+fn transaction_key(shard: &str, system: &str, txid: &str) -> String {
+    format!("decs:{}:{}:transaction:{}", shard, system, txid)
+}
+
+fn completed_key(shard: &str, system: &str, txid: &str) -> String {
+    format!("decs:{}:{}:transaction:{}:completed", shard, system, txid)
+}
+
+fn step_key(shard: &str, system: &str, txid: &str, step: &str) -> String {
+    format!("decs:{}:{}:transaction:{}:{}", shard, system, txid, step)
+}
+
+/// Attempt to claim a transaction ID. Claims expire, so a system that dies mid-payment doesn't
+/// block it forever
+pub fn claim(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    system: &str,
+    txid: &str,
+) -> std::result::Result<Claim, Box<dyn std::error::Error>> {
+    if ctx.kv().exists(&completed_key(shard, system, txid))? {
+        return Ok(Claim::Completed);
+    }
+    if try_lock(ctx, &transaction_key(shard, system, txid), CLAIM_TTL)? {
+        Ok(Claim::Won)
+    } else {
+        Ok(Claim::InFlight)
+    }
+}
+
+/// Attempt to take the lock held in the given key for up to `ttl` seconds. The underlying atomic
+/// increment only returns 1 for the first caller, who then makes the lock expire. Should that
+/// caller die before it gets to, the next caller (the only one to see 2) makes it expire instead,
+/// so a lock can never be held forever
+pub fn try_lock(
+    ctx: &CapabilitiesContext,
+    key: &str,
+    ttl: u32,
+) -> std::result::Result<bool, Box<dyn std::error::Error>> {
+    let count = ctx.kv().atomic_add(key, 1)?;
+    if count == 1 || count == 2 {
+        ctx.kv().set(key, &count.to_string(), Some(ttl))?;
+    }
+    Ok(count == 1)
+}
+
+/// Take the lock held in the given key, making up to `LOCK_ATTEMPTS` attempts
+pub fn lock(
+    ctx: &CapabilitiesContext,
+    key: &str,
+    ttl: u32,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for _ in 0..LOCK_ATTEMPTS {
+        if try_lock(ctx, key, ttl)? {
+            return Ok(());
+        }
+    }
+    Err(format!("{} is locked", key).into())
+}
+
+pub fn unlock(
+    ctx: &CapabilitiesContext,
+    key: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().del_key(key)?;
+    Ok(())
+}
+
+/// The value recorded for one step of a transaction, if that step has been done
+pub fn step(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    system: &str,
+    txid: &str,
+    step: &str,
+) -> std::result::Result<Option<String>, Box<dyn std::error::Error>> {
+    Ok(ctx.kv().get(&step_key(shard, system, txid, step))?)
+}
+
+/// Record that one step of a transaction has been done, along with whatever a retry needs to know
+/// about it. Steps are remembered as long as the completed transaction is
+pub fn record_step(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    system: &str,
+    txid: &str,
+    step: &str,
+    value: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().set(
+        &step_key(shard, system, txid, step),
+        value,
+        Some(COMPLETED_TTL),
+    )?;
+    Ok(())
+}
+
+/// Record that the transaction's credit has been published. This is only done once the wallet is
+/// set, so a failure before that point can never cause the payment to be skipped
+pub fn complete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    system: &str,
+    txid: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    ctx.kv().set(
+        &completed_key(shard, system, txid),
+        "1",
+        Some(COMPLETED_TTL),
+    )?;
+    Ok(())
+}
+
+/// Give up a claim, so the transaction can be attempted again. Any steps already recorded are
+/// skipped by the next attempt
+pub fn release(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    system: &str,
+    txid: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    unlock(ctx, &transaction_key(shard, system, txid))
+}
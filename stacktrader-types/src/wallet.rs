@@ -0,0 +1,153 @@
+//! # Wallets
+//!
+//! Every system that moves credits does so through `update_wallet`, and appends an entry to the
+//! entity's `ledger` collection so that every credit can be audited. Wallets are read, modified
+//! and written back while holding a lock on them, so that systems changing the same wallet at the
+//! same time never overwrite each other's changes. The new balance is written to the KV store
+//! straight away, so the next holder of the lock reads it even before the component manager would
+//! have applied a `set`.
+use crate::components::*;
+use crate::transaction;
+use decscloud_common::gateway::*;
+use guest::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WALLET: &str = "wallet";
+const LEDGER: &str = "ledger";
+/// How long a wallet may stay locked, in seconds, should the system holding it die
+const LOCK_TTL: u32 = 10;
+
+fn wallet_key(shard: &str, entity: &str) -> String {
+    format!("decs:components:{}:{}:{}", shard, entity, WALLET)
+}
+
+fn lock_key(shard: &str, entity: &str) -> String {
+    format!("decs:{}:{}:lock:{}", shard, WALLET, entity)
+}
+
+/// Retrieve the entity's current wallet, or an empty one if the entity has never been paid
+pub fn get_wallet(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<CreditWallet, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&wallet_key(shard, entity))? {
+        Some(s) => Ok(serde_json::from_str(&s)?),
+        None => Ok(CreditWallet::default()),
+    }
+}
+
+/// Apply a change to the entity's wallet while holding the lock on it, returning the new wallet.
+/// Nothing is written if the change fails, e.g. because the entity can't afford it
+pub fn update_wallet<F>(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    change: F,
+) -> std::result::Result<CreditWallet, Box<dyn std::error::Error>>
+where
+    F: FnOnce(CreditWallet) -> std::result::Result<CreditWallet, Box<dyn std::error::Error>>,
+{
+    let entities = [entity.to_string()];
+    lock_wallets(ctx, shard, &entities)?;
+    let updated = get_wallet(ctx, shard, entity)
+        .and_then(change)
+        .and_then(|wallet| store_wallet(ctx, shard, entity, &wallet).map(|_| wallet));
+    unlock_wallets(ctx, shard, &entities)?;
+    updated
+}
+
+/// Lock the wallets of several entities, for changes that have to be made to all of them or none.
+/// They are always locked in the same order, so two systems locking the same wallets can't each
+/// end up holding some of them. If any can't be locked, none are held
+pub fn lock_wallets(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entities: &[String],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut sorted: Vec<_> = entities.iter().collect();
+    sorted.sort();
+    sorted.dedup();
+    for (i, entity) in sorted.iter().enumerate() {
+        if let Err(e) = transaction::lock(ctx, &lock_key(shard, entity), LOCK_TTL) {
+            for entity in &sorted[..i] {
+                transaction::unlock(ctx, &lock_key(shard, entity))?;
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+pub fn unlock_wallets(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entities: &[String],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for entity in entities {
+        transaction::unlock(ctx, &lock_key(shard, entity))?;
+    }
+    Ok(())
+}
+
+/// Write a wallet whose lock is held. An existing wallet is written to the KV store and its
+/// change announced directly, so that a later `set` can never be applied before this one. A new
+/// wallet is created through the component manager, which also registers the component
+pub fn store_wallet(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    wallet: &CreditWallet,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let key = wallet_key(shard, entity);
+    if !ctx.kv().exists(&key)? {
+        ctx.kv().set(&key, &serde_json::to_string(wallet)?, None)?;
+        return publish_wallet(ctx, shard, entity, wallet);
+    }
+    ctx.kv().set(&key, &serde_json::to_string(wallet)?, None)?;
+    let payload = serde_json::json!({ "values": wallet });
+    ctx.msg().publish(
+        &format!(
+            "event.decs.components.{}.{}.{}.change",
+            shard, entity, WALLET
+        ),
+        None,
+        &serde_json::to_vec(&payload)?,
+    )?;
+    Ok(())
+}
+
+/// Publish the given wallet via "component set" operation targeted at the component manager.
+fn publish_wallet(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    wallet: &CreditWallet,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let setreq =
+        ResProtocolRequest::Set(format!("decs.components.{}.{}.{}", shard, entity, WALLET));
+    let payload = serde_json::json!({ "params": wallet });
+    ctx.msg()
+        .publish(&setreq.to_string(), None, &serde_json::to_vec(&payload)?)?;
+    Ok(())
+}
+
+/// Append an entry to the entity's `ledger` collection via the component manager, stamped with the
+/// current time
+pub fn publish_ledger_entry(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    entry: &LedgerEntry,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let entry = LedgerEntry {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+        ..entry.clone()
+    };
+    let newreq =
+        ResProtocolRequest::New(format!("decs.components.{}.{}.{}", shard, entity, LEDGER));
+    let payload = serde_json::json!({ "params": entry });
+    ctx.msg()
+        .publish(&newreq.to_string(), None, &serde_json::to_vec(&payload)?)?;
+    Ok(())
+}
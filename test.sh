@@ -9,6 +9,9 @@ cd genesis && cargo test $1 && echo "Genesis tested" \
 && cd ../physics && cargo test $1 && echo "Physics tested" \
 && cd ../radar && cargo test $1 && echo "Radar tested" \
 && cd ../stacktrader-types && cargo test $1 && echo "Stacktrader-types tested" \
+&& cd ../trade && cargo test $1 && echo "Trade tested" \
+&& cd ../market && cargo test $1 && echo "Market tested" \
+&& cd ../contracts && cargo test $1 && echo "Contracts tested" \
 
 if [ $? -eq 0 ]
 then
@@ -0,0 +1,674 @@
+//! # Trade
+//!
+//! The trade system awaits frames for entities that have a `trade_offer` component, i.e. players
+//! who have offered a trade to another player. Each time it encounters such a frame, it will:
+//! - stamp a new offer with a unique ID from a counter in the KV store, which later serves as the
+//!   trade's transaction ID, and record the offer's terms under that ID
+//! - delete the offer if the counterparty declined it
+//! - carry out the offer if the counterparty accepted it
+//!
+//! The counterparty answers an offer with a `trade_response` component on their own entity that
+//! names the offer's ID. The offer's own `status` is never taken as an answer, because the
+//! offering player can set their own component to anything they like. For the same reason, only
+//! the terms recorded by the trade system when it stamped the offer are ever carried out, and an
+//! offer whose component no longer matches those terms fails rather than being carried out.
+//!
+//! Carrying out a trade claims the trade's ID in the KV store, which guarantees that a retried
+//! frame or a second trade system never applies the same trade twice, and locks both wallets.
+//! It then checks that every item is still in its owner's inventory and that both wallets can
+//! cover their side of the deal, so nothing is published unless the whole trade can be applied.
+//! Finally the items change hands, both wallets are set, both ledgers get an entry and the offer
+//! and response are deleted, all through the component manager. Each of those writes is recorded
+//! as a step of the transaction, so an attempt that fails part way through releases the claim
+//! and the next frame carries on from where it stopped. A trade that can't be carried out is
+//! marked as `failed`.
+use decscloud_common::gateway::*;
+use guest::prelude::*;
+use stacktrader_types as trader;
+use trader::components::*;
+use trader::transaction::{self, Claim};
+use trader::wallet::*;
+
+const TRADE_REASON: &str = "trade";
+/// Transaction step holding the checked settlement, recorded before anything is written
+const SETTLED: &str = "settled";
+
+/// Items taken out of an inventory, keyed by their RID in that inventory
+type Items = Vec<(String, InventoryItem)>;
+
+/// What the trade system should do with an offer on this frame
+#[derive(Debug, PartialEq)]
+enum Step {
+    Register, // Stamp the offer with an ID so that it can be answered
+    Execute,  // The target accepted the offer
+    Decline,  // The target declined the offer
+    Wait,     // Nothing to do until the target answers, or the offerer withdraws a failed offer
+}
+
+/// The terms of an offer as recorded by the trade system when it stamped the offer, kept out of
+/// the players' reach in the KV store
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+struct Terms {
+    offerer: String,
+    target: String,
+    offered_items: Vec<String>,
+    offered_credits: Credits,
+    requested_items: Vec<String>,
+    requested_credits: Credits,
+}
+
+impl Terms {
+    fn new(offerer: &str, offer: &TradeOffer) -> Terms {
+        Terms {
+            offerer: offerer.to_string(),
+            target: offer.target.clone(),
+            offered_items: offer.offered_items.clone(),
+            offered_credits: offer.offered_credits,
+            requested_items: offer.requested_items.clone(),
+            requested_credits: offer.requested_credits,
+        }
+    }
+}
+
+/// Receives an entity, shard, elapsed time, etc from an EntityFrame
+/// published on decs.frames.{shard}.{system}, e.g. `decs.frames.the_void.trade`
+pub(crate) fn handle_frame(
+    ctx: &CapabilitiesContext,
+    msg: guest::prelude::messaging::BrokerMessage,
+) -> CallResult {
+    let subject: Vec<&str> = msg.subject.split('.').collect();
+    if subject.len() != 4 {
+        return Err("Unknown message subject received".into());
+    }
+    let frame: decs::systemmgr::EntityFrame = serde_json::from_slice(&msg.body)?;
+    let offer = match get_offer(ctx, &frame.shard, &frame.entity_id)? {
+        Some(offer) => offer,
+        None => return Ok(vec![]),
+    };
+    let response = if offer.id.is_empty() {
+        None
+    } else {
+        get_response(ctx, &frame.shard, &offer.target)?
+    };
+
+    match next_step(&offer, response.as_ref()) {
+        Step::Register => {
+            let id = ctx
+                .kv()
+                .atomic_add(&format!("decs:{}:trade:next_id", frame.shard), 1)?;
+            let offer = TradeOffer {
+                id: id.to_string(),
+                status: TradeStatus::Pending,
+                ..offer
+            };
+            ctx.kv().set(
+                &terms_key(&frame.shard, &offer.id),
+                &serde_json::to_string(&Terms::new(&frame.entity_id, &offer))?,
+                None,
+            )?;
+            publish_offer(ctx, &frame.shard, &frame.entity_id, &offer)?;
+        }
+        Step::Execute => execute_trade(ctx, &frame, offer)?,
+        Step::Decline => {
+            publish_response_delete(ctx, &frame.shard, &offer.target)?;
+            publish_offer_delete(ctx, &frame.shard, &frame.entity_id)?;
+            ctx.kv().del_key(&terms_key(&frame.shard, &offer.id))?;
+        }
+        Step::Wait => {}
+    }
+
+    Ok(vec![])
+}
+
+/// Decide what to do with an offer given the target's response, if they have one. A response
+/// only counts if it names this offer, so an answer to an earlier offer is never applied to a
+/// later one
+fn next_step(offer: &TradeOffer, response: Option<&TradeResponse>) -> Step {
+    if offer.id.is_empty() {
+        return Step::Register;
+    }
+    if offer.status == TradeStatus::Failed {
+        return Step::Wait;
+    }
+    match response {
+        Some(r) if r.offer == offer.id => match r.status {
+            TradeStatus::Accepted => Step::Execute,
+            TradeStatus::Declined => Step::Decline,
+            TradeStatus::Pending | TradeStatus::Failed => Step::Wait,
+        },
+        _ => Step::Wait,
+    }
+}
+
+fn terms_key(shard: &str, id: &str) -> String {
+    format!("decs:{}:trade:offers:{}", shard, id)
+}
+
+/// Retrieve the terms recorded for the offer with the given ID
+fn get_terms(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    id: &str,
+) -> std::result::Result<Option<Terms>, Box<dyn std::error::Error>> {
+    match ctx.kv().get(&terms_key(shard, id))? {
+        Some(s) => Ok(Some(serde_json::from_str(&s)?)),
+        None => Ok(None),
+    }
+}
+
+/// Everything needed to apply an accepted trade, gathered and checked before anything is written
+#[derive(Serialize, Deserialize, Debug)]
+struct Settlement {
+    offered_items: Items,
+    requested_items: Items,
+    offerer_delta: Credits, // Net change to the offering player's credits
+}
+
+/// Carry out an accepted trade according to its recorded terms. Only the holder of the trade's
+/// claim gets to apply it, and it does so while holding the locks on both players' wallets, so
+/// that no other system changes either of them in between reading and writing them
+fn execute_trade(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+    offer: TradeOffer,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let shard = &frame.shard;
+    let offerer = &frame.entity_id;
+    let terms = match get_terms(ctx, shard, &offer.id)? {
+        Some(terms) => terms,
+        None => return publish_offer_failed(ctx, shard, offerer, offer, "offer is not on record"),
+    };
+    if terms != Terms::new(offerer, &offer) {
+        let reason = "offer was changed after it was made";
+        return publish_offer_failed(ctx, shard, offerer, offer, reason);
+    }
+
+    match transaction::claim(ctx, shard, super::SYSTEM_NAME, &offer.id)? {
+        Claim::Won => {}
+        // A previous attempt already applied this trade but didn't get to remove the offer
+        Claim::Completed => return remove_offer(ctx, shard, &terms, &offer.id),
+        Claim::InFlight => return Ok(()),
+    }
+    let parties = [terms.offerer.clone(), terms.target.clone()];
+    if let Err(e) = lock_wallets(ctx, shard, &parties) {
+        transaction::release(ctx, shard, super::SYSTEM_NAME, &offer.id)?;
+        return Err(e);
+    }
+    let applied = apply_trade(ctx, frame, &offer.id, &terms);
+    let unlocked = unlock_wallets(ctx, shard, &parties);
+
+    match applied.and_then(|applied| unlocked.map(|_| applied)) {
+        Ok(None) => {
+            transaction::complete(ctx, shard, super::SYSTEM_NAME, &offer.id)?;
+            remove_offer(ctx, shard, &terms, &offer.id)
+        }
+        Ok(Some(reason)) => {
+            transaction::release(ctx, shard, super::SYSTEM_NAME, &offer.id)?;
+            publish_offer_failed(ctx, shard, offerer, offer, &reason)
+        }
+        Err(e) => {
+            // Let the next frame carry on with the trade from wherever this attempt stopped
+            transaction::release(ctx, shard, super::SYSTEM_NAME, &offer.id)?;
+            Err(e)
+        }
+    }
+}
+
+/// Apply the trade, skipping any step a previous attempt already recorded. Returns the reason the
+/// trade can't be carried out if it fails its checks, in which case nothing has been written
+fn apply_trade(
+    ctx: &CapabilitiesContext,
+    frame: &decs::systemmgr::EntityFrame,
+    txid: &str,
+    terms: &Terms,
+) -> std::result::Result<Option<String>, Box<dyn std::error::Error>> {
+    let shard = &frame.shard;
+    let step = |name: &str| transaction::step(ctx, shard, super::SYSTEM_NAME, txid, name);
+    let record = |name: &str, value: &str| {
+        transaction::record_step(ctx, shard, super::SYSTEM_NAME, txid, name, value)
+    };
+
+    let settlement: Settlement = match step(SETTLED)? {
+        Some(s) => serde_json::from_str(&s)?,
+        None => {
+            let checked = get_items(ctx, shard, &terms.offerer, &terms.offered_items).and_then(
+                |offered| {
+                    let requested = get_items(ctx, shard, &terms.target, &terms.requested_items)?;
+                    let offerer_wallet = get_wallet(ctx, shard, &terms.offerer)?;
+                    let target_wallet = get_wallet(ctx, shard, &terms.target)?;
+                    settle(terms, (offered, requested), offerer_wallet, target_wallet)
+                },
+            );
+            match checked {
+                Ok(s) => {
+                    record(SETTLED, &serde_json::to_string(&s)?)?;
+                    s
+                }
+                Err(e) => return Ok(Some(e.to_string())),
+            }
+        }
+    };
+
+    let moves = settlement
+        .offered_items
+        .into_iter()
+        .map(|item| (&terms.offerer, &terms.target, item))
+        .chain(
+            settlement
+                .requested_items
+                .into_iter()
+                .map(|item| (&terms.target, &terms.offerer, item)),
+        );
+    for (from, to, (rid, item)) in moves {
+        let moved = format!("moved.{}", rid);
+        if step(&moved)?.is_none() {
+            move_item(ctx, shard, from, to, &rid, item)?;
+            record(&moved, "1")?;
+        }
+    }
+    if settlement.offerer_delta != 0 {
+        let sides = [
+            (&terms.offerer, &terms.target, settlement.offerer_delta),
+            (&terms.target, &terms.offerer, -settlement.offerer_delta),
+        ];
+        for (entity, counterparty, amount) in sides.iter() {
+            let paid = format!("paid.{}", entity);
+            let balance = match step(&paid)? {
+                Some(balance) => balance.parse()?,
+                None => {
+                    // Applied as a change to the current balance rather than set to the balance
+                    // the checks worked out, in case a failed attempt released the lock in between
+                    let wallet = get_wallet(ctx, shard, entity)?
+                        .checked_add(*amount)
+                        .ok_or("trade would overflow the wallet")?;
+                    store_wallet(ctx, shard, entity, &wallet)?;
+                    record(&paid, &wallet.credits.to_string())?;
+                    wallet.credits
+                }
+            };
+            let posted = format!("posted.{}", entity);
+            if step(&posted)?.is_none() {
+                let entry = LedgerEntry {
+                    txid: txid.to_string(),
+                    seq_no: frame.seq_no,
+                    amount: *amount,
+                    balance,
+                    counterparty: counterparty.to_string(),
+                    item: None,
+                    reason: TRADE_REASON.to_string(),
+                    ..Default::default()
+                };
+                publish_ledger_entry(ctx, shard, entity, &entry)?;
+                record(&posted, "1")?;
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Remove a carried out offer along with the target's answer and the recorded terms
+fn remove_offer(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    terms: &Terms,
+    id: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    publish_response_delete(ctx, shard, &terms.target)?;
+    publish_offer_delete(ctx, shard, &terms.offerer)?;
+    ctx.kv().del_key(&terms_key(shard, id))?;
+    Ok(())
+}
+
+/// Check that the trade can be carried out in full given the gathered items and both players'
+/// current wallets, working out the net change to the offering player's credits
+fn settle(
+    terms: &Terms,
+    items: (Items, Items), // The items offered and requested
+    offerer_wallet: CreditWallet,
+    target_wallet: CreditWallet,
+) -> std::result::Result<Settlement, Box<dyn std::error::Error>> {
+    if terms.target == terms.offerer {
+        return Err("cannot trade with yourself".into());
+    }
+    if terms.offered_credits < 0 || terms.requested_credits < 0 {
+        return Err("credit amounts cannot be negative".into());
+    }
+    let (offered_items, requested_items) = items;
+
+    let offerer_delta = terms
+        .requested_credits
+        .checked_sub(terms.offered_credits)
+        .ok_or("credit amounts are too large")?;
+    let offerer_wallet = offerer_wallet
+        .checked_add(offerer_delta)
+        .ok_or("trade would overflow the offering wallet")?;
+    let target_wallet = target_wallet
+        .checked_add(-offerer_delta)
+        .ok_or("trade would overflow the receiving wallet")?;
+    if offerer_wallet.credits < 0 {
+        return Err(format!("{} cannot afford this trade", terms.offerer).into());
+    }
+    if target_wallet.credits < 0 {
+        return Err(format!("{} cannot afford this trade", terms.target).into());
+    }
+
+    Ok(Settlement {
+        offered_items,
+        requested_items,
+        offerer_delta,
+    })
+}
+
+/// Retrieve the given items, all of which must currently be in the owner's inventory
+fn get_items(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    owner: &str,
+    rids: &[String],
+) -> std::result::Result<Items, Box<dyn std::error::Error>> {
+    let inventory = ctx.kv().list_range(
+        &format!("decs:components:{}:{}:{}", shard, owner, super::INVENTORY),
+        0,
+        -1,
+    )?;
+    collect_items(owner, rids, &inventory, |rid| {
+        Ok(ctx.kv().get(&rid.replace('.', ":"))?)
+    })
+}
+
+/// Gather the given items out of the owner's inventory, looking up each item's contents with
+/// `get`. Fails if an item is listed twice, isn't in the inventory or no longer exists
+fn collect_items<F>(
+    owner: &str,
+    rids: &[String],
+    inventory: &[String],
+    mut get: F,
+) -> std::result::Result<Items, Box<dyn std::error::Error>>
+where
+    F: FnMut(&str) -> std::result::Result<Option<String>, Box<dyn std::error::Error>>,
+{
+    let mut items = Vec::new();
+    for rid in rids {
+        if items.iter().any(|(r, _)| r == rid) {
+            return Err(format!("{} is listed more than once", rid).into());
+        }
+        let raw = if inventory.contains(rid) {
+            get(rid)?
+        } else {
+            None
+        };
+        match raw {
+            Some(s) => items.push((rid.clone(), serde_json::from_str(&s)?)),
+            None => return Err(format!("{} is no longer in {}'s inventory", rid, owner).into()),
+        }
+    }
+    Ok(items)
+}
+
+fn get_offer(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<Option<TradeOffer>, Box<dyn std::error::Error>> {
+    let key = format!("decs:components:{}:{}:{}", shard, entity, super::TRADE_OFFER);
+    match ctx.kv().get(&key)? {
+        Some(s) => Ok(Some(serde_json::from_str(&s)?)),
+        None => Ok(None),
+    }
+}
+
+fn get_response(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<Option<TradeResponse>, Box<dyn std::error::Error>> {
+    let key = format!(
+        "decs:components:{}:{}:{}",
+        shard,
+        entity,
+        super::TRADE_RESPONSE
+    );
+    match ctx.kv().get(&key)? {
+        Some(s) => Ok(Some(serde_json::from_str(&s)?)),
+        None => Ok(None),
+    }
+}
+
+/// Add the item to the recipient's inventory and remove it from the owner's inventory
+fn move_item(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    from: &str,
+    to: &str,
+    rid: &str,
+    item: InventoryItem,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let item = InventoryItem { error: None, ..item };
+    let newreq = ResProtocolRequest::New(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        to,
+        super::INVENTORY
+    ));
+    ctx.msg().publish(
+        &newreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({ "params": item }))?,
+    )?;
+    let delreq = ResProtocolRequest::Delete(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        from,
+        super::INVENTORY
+    ));
+    ctx.msg().publish(
+        &delreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({"params": {"rid": rid}}))?,
+    )?;
+    Ok(())
+}
+
+fn publish_offer(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    offer: &TradeOffer,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let setreq = ResProtocolRequest::Set(format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::TRADE_OFFER
+    ));
+    ctx.msg().publish(
+        &setreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({ "params": offer }))?,
+    )?;
+    Ok(())
+}
+
+/// Leave the offer in place, marked as failed along with the reason, for the players to see
+fn publish_offer_failed(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+    offer: TradeOffer,
+    reason: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let offer = TradeOffer {
+        status: TradeStatus::Failed,
+        error: Some(reason.to_string()),
+        ..offer
+    };
+    publish_offer(ctx, shard, entity, &offer)
+}
+
+fn publish_offer_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let rid = format!("decs.components.{}.{}.{}", shard, entity, super::TRADE_OFFER);
+    let delreq = ResProtocolRequest::Delete(rid.clone());
+    ctx.msg().publish(
+        &delreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({"params": {"rid": rid}}))?,
+    )?;
+    Ok(())
+}
+
+/// Delete the target's answer once the offer it names has been carried out or declined
+fn publish_response_delete(
+    ctx: &CapabilitiesContext,
+    shard: &str,
+    entity: &str,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let rid = format!(
+        "decs.components.{}.{}.{}",
+        shard,
+        entity,
+        super::TRADE_RESPONSE
+    );
+    let delreq = ResProtocolRequest::Delete(rid.clone());
+    ctx.msg().publish(
+        &delreq.to_string(),
+        None,
+        &serde_json::to_vec(&serde_json::json!({"params": {"rid": rid}}))?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{collect_items, next_step, settle, Step, Terms};
+    use stacktrader_types::components::*;
+
+    fn offer(id: &str, status: TradeStatus) -> TradeOffer {
+        TradeOffer {
+            id: id.to_string(),
+            target: "bob".to_string(),
+            status,
+            ..Default::default()
+        }
+    }
+
+    fn response(offer: &str, status: TradeStatus) -> TradeResponse {
+        TradeResponse {
+            offer: offer.to_string(),
+            status,
+        }
+    }
+
+    fn wallet(credits: Credits) -> CreditWallet {
+        CreditWallet { credits }
+    }
+
+    #[test]
+    fn test_next_step() {
+        let pending = offer("7", TradeStatus::Pending);
+        let accepted = response("7", TradeStatus::Accepted);
+
+        assert_eq!(
+            Step::Register,
+            next_step(&offer("", TradeStatus::Pending), None)
+        );
+        assert_eq!(Step::Wait, next_step(&pending, None));
+        assert_eq!(Step::Execute, next_step(&pending, Some(&accepted)));
+        assert_eq!(
+            Step::Decline,
+            next_step(&pending, Some(&response("7", TradeStatus::Declined)))
+        );
+        // An answer to some other offer doesn't count
+        assert_eq!(
+            Step::Wait,
+            next_step(&pending, Some(&response("6", TradeStatus::Accepted)))
+        );
+        // The offerer marking their own offer as accepted doesn't count either
+        assert_eq!(
+            Step::Wait,
+            next_step(&offer("7", TradeStatus::Accepted), None)
+        );
+        assert_eq!(
+            Step::Wait,
+            next_step(&offer("7", TradeStatus::Failed), Some(&accepted))
+        );
+        assert_eq!(
+            Step::Register,
+            next_step(&offer("", TradeStatus::Accepted), Some(&accepted))
+        );
+    }
+
+    #[test]
+    fn test_collect_items() {
+        let inventory = vec!["inv.1".to_string(), "inv.2".to_string()];
+        let get = |rid: &str| {
+            Ok(match rid {
+                "inv.1" => Some(r#"{"stack_type":"tasty","qty":3}"#.to_string()),
+                _ => None,
+            })
+        };
+
+        let items = collect_items("alice", &["inv.1".to_string()], &inventory, get).unwrap();
+        assert_eq!(1, items.len());
+        assert_eq!("inv.1", items[0].0);
+        assert_eq!(3, items[0].1.resource.qty);
+
+        let twice = vec!["inv.1".to_string(), "inv.1".to_string()];
+        assert!(collect_items("alice", &twice, &inventory, get).is_err());
+        // Listed in the inventory, but the item itself is gone
+        assert!(collect_items("alice", &["inv.2".to_string()], &inventory, get).is_err());
+        // Not in the owner's inventory at all
+        assert!(collect_items("alice", &["inv.3".to_string()], &inventory, get).is_err());
+    }
+
+    #[test]
+    fn test_settle() {
+        let mut trade = offer("7", TradeStatus::Pending);
+        trade.offered_credits = 30;
+        trade.requested_credits = 10;
+        let mut terms = Terms::new("alice", &trade);
+
+        let s = settle(&terms, (vec![], vec![]), wallet(50), wallet(5)).unwrap();
+        assert_eq!(-20, s.offerer_delta);
+
+        assert!(settle(&terms, (vec![], vec![]), wallet(15), wallet(5)).is_err());
+        assert!(settle(
+            &Terms::new("bob", &trade),
+            (vec![], vec![]),
+            wallet(50),
+            wallet(5)
+        )
+        .is_err());
+
+        terms.requested_credits = 100;
+        assert!(settle(&terms, (vec![], vec![]), wallet(50), wallet(5)).is_err());
+
+        terms.offered_credits = -1;
+        assert!(settle(&terms, (vec![], vec![]), wallet(50), wallet(500)).is_err());
+
+        terms.offered_credits = 0;
+        terms.requested_credits = 1;
+        assert!(settle(&terms, (vec![], vec![]), wallet(Credits::MAX), wallet(5)).is_err());
+    }
+
+    #[test]
+    fn test_terms() {
+        let mut trade = offer("7", TradeStatus::Pending);
+        trade.offered_credits = 30;
+        let terms = Terms::new("alice", &trade);
+
+        // Answering the offer or marking it failed leaves its terms alone
+        trade.status = TradeStatus::Failed;
+        trade.error = Some("no".to_string());
+        assert_eq!(terms, Terms::new("alice", &trade));
+
+        trade.offered_credits = 3;
+        assert_ne!(terms, Terms::new("alice", &trade));
+        trade.offered_credits = 30;
+        trade.requested_items.push("inv.1".to_string());
+        assert_ne!(terms, Terms::new("alice", &trade));
+    }
+}
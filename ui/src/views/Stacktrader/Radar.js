@@ -1,5 +1,18 @@
 import React, { Component } from 'react';
 
+// How a contact is displayed until its transponder can be read
+export const UNKNOWN_CONTACT = {
+    color: "#8f9ba6",
+    display_name: "Unknown contact",
+    object_type: "unknown"
+}
+
+// The RID a contact is targeted through: the entity's own once it's identified, until then the
+// handle the player's receiver lists it under, which only radar can tell the entity from
+export const contactRid = (shard, receiver, contact) => contact.transponder
+    ? `decs.components.${shard}.${contact.entity_id}`
+    : `decs.${shard}.radar.${receiver}.${contact.entity_id}`
+
 class Radar extends Component {
 
     constructor(props) {
@@ -37,7 +50,7 @@ class Radar extends Component {
     }
 
     targetEntity = (_event, contact) => {
-        this.props.navigateToTarget(`decs.components.${this.props.shard}.${contact.entity_id}`)
+        this.props.navigateToTarget(contactRid(this.props.shard, this.props.entity, contact))
     }
 
     render() {
@@ -52,17 +65,22 @@ class Radar extends Component {
                 x = radar_radius + (xOffset * radar_radius / radar_receiver_radius),
                 y = radar_radius + (yOffset * radar_radius / radar_receiver_radius),
                 delay = time / 360 * contact.azimuth;
+            // Contacts without a transponder link haven't been identified
+            let transponder = contact.transponder || UNKNOWN_CONTACT
             let style = {
                 left: x,
                 top: y,
-                color: contact.transponder.color,
+                color: transponder.color,
+                // Faint blips for contacts that are barely detected
+                opacity: contact.confidence === undefined ? 1 : 0.3 + 0.7 * contact.confidence,
                 '-webkit-animation-delay': delay + 's',
                 'animation-delay': delay + 's'
             }
-            let icon = contact.transponder.object_type === "asteroid" ? "fa-bullseye" :
-                contact.transponder.object_type === "ship" ? "fa-space-shuttle" :
-                    contact.transponder.object_type === "starbase" ? "fa-fort-awesome" : "fa-warning"
-            return <span style={style} className={`dot radar-icon fa ${icon} fa-lg`} onClick={(e) => this.targetEntity(e, contact)}></span>
+            let icon = transponder.object_type === "asteroid" ? "fa-bullseye" :
+                transponder.object_type === "ship" ? "fa-space-shuttle" :
+                    transponder.object_type === "starbase" ? "fa-fort-awesome" :
+                        transponder.object_type === "unknown" ? "fa-question-circle" : "fa-warning"
+            return <span style={style} title={contact.relayed ? "Relayed by your sensor network" : undefined} className={`dot radar-icon fa ${icon} fa-lg`} onClick={(e) => this.targetEntity(e, contact)}></span>
         })
         return (
             <div id="radar-container">
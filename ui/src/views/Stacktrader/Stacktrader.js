@@ -14,7 +14,7 @@ import {
   ModalFooter
 } from 'reactstrap';
 import { Redirect } from 'react-router-dom';
-import Radar from './Radar'
+import Radar, { UNKNOWN_CONTACT, contactRid } from './Radar'
 import Inventory from './Inventory'
 import { ToastContainer, toast } from 'react-toastify';
 import 'react-toastify/dist/ReactToastify.css';
@@ -54,6 +54,9 @@ class Stacktrader extends Component {
       mining_resource_eta_ms: 0,
       recently_mined: null,
       display_name: "",
+      transponder: null,
+      transponder_active: true,
+      trade_radius: 5.0,
       tutorial: false
     };
   }
@@ -135,7 +138,7 @@ class Stacktrader extends Component {
     let velocity = { mag, ux, uy, uz }
 
     this.client.call(`decs.components.${this.state.shard}.${this.state.entity_id}.velocity`, 'set', velocity).then(_res => {
-      this.setTarget(`decs.components.${this.state.shard}.${contact.entity_id}`)
+      this.setTarget(contactRid(this.state.shard, this.state.entity_id, contact))
     })
   }
 
@@ -169,7 +172,9 @@ class Stacktrader extends Component {
    * Take an item from a player's inventory and add it to the sell list for merchant processing
    */
   sellItem = (item) => {
-    this.client.call(`decs.components.${this.state.shard}.${this.state.entity_id}.sell_list`, 'new', item).then(_res => {
+    // Drop the reason an earlier sale was refused, or the merchant would hand the item straight back
+    let { error, ...params } = item.toJSON()
+    this.client.call(`decs.components.${this.state.shard}.${this.state.entity_id}.sell_list`, 'new', params).then(_res => {
       this.client.call(`decs.components.${this.state.shard}.${this.state.entity_id}.inventory`, 'delete', { rid: item._rid })
       this.client.get(`decs.components.${this.state.shard}.${this.state.entity_id}.sell_list`).then(sell_list => {
         sell_list.on('remove', () => {
@@ -185,10 +190,63 @@ class Stacktrader extends Component {
     })
   }
 
+  /**
+   * Switches the player's transponder on or off. Switched off, other players only see an unknown
+   * contact until they come within identification range. A set replaces the whole component, so
+   * the rest of the transponder is sent along unchanged
+   */
+  toggleTransponder = () => {
+    if (!this.state.transponder) {
+      return
+    }
+    let active = !this.state.transponder_active
+    this.client.call(`decs.components.${this.state.shard}.${this.state.entity_id}.transponder`, 'set', {
+      ...this.state.transponder.toJSON(),
+      active
+    }).then(_res => {
+      this.setState({ transponder_active: active })
+    }).catch(err => {
+      console.log(err)
+    })
+  }
+
+  /**
+   * Shows or hides a type of radar contact, or depleted asteroids when given "depleted". A set
+   * replaces the whole component, so the rest of the receiver is sent along unchanged
+   */
+  toggleRadarFilter = (filter) => {
+    let radar_receiver = this.state.radar_receiver
+    if (!radar_receiver.toJSON) {
+      return
+    }
+    let object_types = Array.from(radar_receiver.object_types || [])
+    let hide_depleted = !!radar_receiver.hide_depleted
+    if (filter === "depleted") {
+      hide_depleted = !hide_depleted
+    } else if (object_types.includes(filter)) {
+      object_types = object_types.filter(t => t !== filter)
+    } else {
+      object_types.push(filter)
+    }
+    this.client.call(`decs.components.${this.state.shard}.${this.state.entity_id}.radar_receiver`, 'set', {
+      ...radar_receiver.toJSON(),
+      object_types,
+      hide_depleted
+    }).catch(err => {
+      console.log(err)
+    })
+  }
+
   /**
    * Helper function to set a Target's UI friendly name from its rid
    */
   getNameForRid = (rid) => {
+    // An unidentified contact's name isn't known until it comes within identification range
+    let contact = this.state.contacts && Array.from(this.state.contacts).find(c => contactRid(this.state.shard, this.state.entity_id, c) === rid)
+    if (!rid.startsWith("decs.components.") || (contact && !contact.transponder)) {
+      this.setState({ target_name: UNKNOWN_CONTACT.display_name })
+      return
+    }
     this.client.get(`${rid}.transponder`).then(transponder => {
       this.setState({ target_name: transponder.display_name })
     }).catch(err => {
@@ -202,8 +260,8 @@ class Stacktrader extends Component {
   withinStarbaseRange = () => {
     let contacts = Array.from(this.state.contacts)
     for (let i = 0; i < contacts.length; i++) {
-      if (contacts[i].entity_id === "starbase_0") {
-        return contacts[i].distance <= 5.0;
+      if (contacts[i].transponder && contacts[i].transponder.object_type === "starbase" && contacts[i].distance <= this.state.trade_radius) {
+        return true;
       }
     }
     return false;
@@ -284,6 +342,9 @@ class Stacktrader extends Component {
             <Card className="card-accent-primary">
               <CardHeader>
                 {this.state.entity_id} - {this.state.display_name}
+                <Button className="float-right" color={this.state.transponder_active ? "success" : "secondary"} size="sm" onClick={this.toggleTransponder}>
+                  Transponder {this.state.transponder_active ? "on" : "off"}
+                </Button>
               </CardHeader>
               <CardBody>
                 <Row style={{ marginRight: '0px', marginLeft: '0px' }}>
@@ -456,6 +517,15 @@ class Stacktrader extends Component {
             <Card className="card-accent-info">
               <CardHeader>
                 Radar Contacts
+                <div className="float-right">
+                  {["ship", "starbase", "asteroid", "unknown"].map(object_type =>
+                    <Button key={object_type} style={{ marginLeft: '2px' }} size="sm"
+                      color={this.state.radar_receiver.object_types && this.state.radar_receiver.object_types.includes(object_type) ? "primary" : "secondary"}
+                      onClick={() => this.toggleRadarFilter(object_type)}>{object_type}</Button>
+                  )}
+                  <Button style={{ marginLeft: '2px' }} size="sm" color={this.state.radar_receiver.hide_depleted ? "primary" : "secondary"}
+                    onClick={() => this.toggleRadarFilter("depleted")}>hide depleted</Button>
+                </div>
               </CardHeader>
               <CardBody>
                 <br />
@@ -468,31 +538,37 @@ class Stacktrader extends Component {
                       <th>Distance</th>
                       <th className="text-center">Angle</th>
                       <th className="text-center">Elevation</th>
+                      <th>Closing</th>
+                      <th>Closest in</th>
                     </tr>
                   </thead>
                   <tbody>
-                    {this.state.contacts && Array.from(this.state.contacts).filter(c => c.transponder).sort((a, b) => a.distance - b.distance).map((contact, _idx) =>
-                      <tr className={this.state.target && this.state.target.rid.split(".")[3] === contact.transponder._rid.split(".")[3] ? "table-success" : ""}>
+                    {this.state.contacts && Array.from(this.state.contacts).sort((a, b) => a.distance - b.distance).map((contact, _idx) => ({ contact, transponder: contact.transponder || UNKNOWN_CONTACT })).map(({ contact, transponder }) =>
+                      <tr className={this.state.target && this.state.target.rid === contactRid(this.state.shard, this.state.entity_id, contact) ? "table-success" : ""}>
                         <td className="text-center">
                           <div>
                             <span style={{
                               position: "relative",
-                              color: contact.transponder.color,
-                              transform: `rotate(${contact.transponder.object_type === "ship" ? 180 : 0}deg)`
-                            }} className={`${contact.transponder.object_type === "asteroid" ? "fa fa-bullseye" :
-                              contact.transponder.object_type === "ship" ? "fa fa-space-shuttle" :
-                                contact.transponder.object_type === "starbase" ? "fa fa-fort-awesome" : "fa fa-warning"} fa-lg`}></span>
+                              color: transponder.color,
+                              transform: `rotate(${transponder.object_type === "ship" ? 180 : 0}deg)`
+                            }} className={`${transponder.object_type === "asteroid" ? "fa fa-bullseye" :
+                              transponder.object_type === "ship" ? "fa fa-space-shuttle" :
+                                transponder.object_type === "starbase" ? "fa fa-fort-awesome" :
+                                  transponder.object_type === "unknown" ? "fa fa-question-circle" : "fa fa-warning"} fa-lg`}></span>
                           </div>
                         </td>
                         <td>
-                          <div>{contact.transponder.display_name}</div>
+                          <div>
+                            {transponder.display_name}
+                            {contact.relayed && <small className="text-muted"> (relayed)</small>}
+                          </div>
                         </td>
                         <td>
                           <Row style={{ marginLeft: '0px', marginRight: '0px' }}>
                             <Button style={{ marginRight: '2px' }} color="success" size="sm" onClick={() => this.navigateToTarget(contact)}>Navigate</Button>
-                            {contact.transponder.object_type === "asteroid" &&
+                            {transponder.object_type === "asteroid" &&
                               this.withinAsteroidRange(contact) &&
-                              !contact.transponder.display_name.includes("(depleted)") &&
+                              !transponder.display_name.includes("(depleted)") &&
                               <Button style={{ marginRight: '2px' }} color="warning" size="sm" onClick={() =>
                                 this.extractResource(`decs.components.${this.state.shard}.${contact.entity_id}`)
                               }>Mine</Button>
@@ -516,6 +592,16 @@ class Stacktrader extends Component {
                             <i className={`${contact.elevation === 90 ? "icon-arrow-dot-circle" : contact.elevation < 90 ? "icon-arrow-up-circle" : "icon-arrow-down-circle"} font-2xl`}></i>
                           </div>
                         </td>
+                        <td>
+                          <div className={contact.closing_speed > 0 ? "text-danger" : ""}>
+                            {Math.round(contact.closing_speed || 0)}km/h
+                          </div>
+                        </td>
+                        <td>
+                          <div>
+                            {contact.closest_approach_secs ? `${Math.floor(contact.closest_approach_secs / 60)}m ${Math.round(contact.closest_approach_secs % 60)}s` : "N/A"}
+                          </div>
+                        </td>
                       </tr>
                     )}
                   </tbody>
@@ -565,7 +651,14 @@ class Stacktrader extends Component {
     })
 
     this.client.get(`decs.components.${shard}.${entity_id}.transponder`).then(transponder => {
-      this.setState({ display_name: transponder.display_name })
+      this.setState({ transponder, display_name: transponder.display_name, transponder_active: transponder.active !== false })
+    }).catch(err => {
+      console.log(err)
+    })
+
+    // Starbases trade within the radius configured for the merchant, if there is one
+    this.client.get(`decs.components.${shard}.universe.merchant`).then(merchant => {
+      this.setState({ trade_radius: merchant.trade_radius || this.state.trade_radius })
     }).catch(err => {
       console.log(err)
     })
@@ -605,7 +698,8 @@ class Stacktrader extends Component {
               this.client.call(`decs.components.${shard}.${entity_id}.transponder`, 'set', {
                 color: "#63c2de",
                 display_name,
-                object_type: "ship"
+                object_type: "ship",
+                active: true
               })
               this.loadPlayer(entity_id, shard)
             })
@@ -629,6 +723,10 @@ class Stacktrader extends Component {
       inventory.on('add', (add) => {
         if (!add.item) {
           return
+        } else if (add.item.error) {
+          // The merchant hands back items it refused to buy, along with the reason
+          toast.error(`Couldn't sell ${add.item.qty} ${add.item.stack_type}: ${add.item.error}`)
+          this.onUpdate()
         } else if (add.item.qty && add.item.stack_type) {
           this.setState({
             recently_mined: {
@@ -654,9 +752,16 @@ class Stacktrader extends Component {
    */
   setupRadarContacts(entity) {
     this.client.get(`decs.components.${this.state.shard}.${entity}.radar_contacts`).then(contacts => {
-      contacts.on('add', this.onUpdate)
-      contacts.on('remove', this.onUpdate)
-      contacts.on('change', this.onUpdate)
+      // Radar sends a change event on the contact itself whenever it moves
+      Array.from(contacts).forEach(contact => contact.on('change', this.onUpdate))
+      contacts.on('add', (add) => {
+        add.item.on('change', this.onUpdate)
+        this.onUpdate()
+      })
+      contacts.on('remove', (remove) => {
+        remove.item.off('change', this.onUpdate)
+        this.onUpdate()
+      })
       this.setState({ contacts })
     }).catch(_err => {
       setTimeout(() => this.setupRadarContacts(entity), 1000)